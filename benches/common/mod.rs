@@ -0,0 +1,28 @@
+//! The measuring loop shared by the benchmarks.
+
+use std::time::{Duration, Instant};
+
+/// Runs `f` for about a second and prints how many times it ran per second.
+///
+/// Arguments that don't start with `-` filter the cases by name, as in
+/// `cargo bench --bench scripts -- fib`. Returns `None` for a case that was
+/// filtered out.
+pub fn bench(name: &str, mut f: impl FnMut()) -> Option<f64> {
+    let filters: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+    if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+        return None;
+    }
+    f();
+    let start = Instant::now();
+    let mut runs = 0u64;
+    while start.elapsed() < Duration::from_secs(1) {
+        f();
+        runs += 1;
+    }
+    let per_second = runs as f64 / start.elapsed().as_secs_f64();
+    println!("{:<24} {:>14.1} ops/s", name, per_second);
+    Some(per_second)
+}
@@ -0,0 +1,62 @@
+//! Scanning and parsing throughput on a multi-megabyte script.
+//!
+//! Run with `cargo bench --bench parsing`. The script is the test corpus
+//! repeated until it is a few megabytes long. Each case reports how many
+//! times it went through the whole script per second and at what rate.
+//! `parse` starts from tokens scanned beforehand, so it measures the parser
+//! alone.
+
+mod common;
+
+use common::bench;
+use rlox::{Parser, Scanner};
+use std::hint::black_box;
+
+const CORPUS: [&str; 4] = [
+    include_str!("../tests/corpus/arithmetic.lox"),
+    include_str!("../tests/corpus/collections.lox"),
+    include_str!("../tests/corpus/control.lox"),
+    include_str!("../tests/corpus/functions.lox"),
+];
+
+const SIZE: usize = 4 << 20;
+
+fn throughput(per_second: Option<f64>, bytes: usize) {
+    if let Some(per_second) = per_second {
+        let megabytes = per_second * bytes as f64 / (1 << 20) as f64;
+        println!("{:<24} {:>14.1} MB/s", "", megabytes);
+    }
+}
+
+fn main() {
+    let mut source = String::new();
+    while source.len() < SIZE {
+        for script in CORPUS {
+            source.push_str(script);
+        }
+    }
+    let tokens = Scanner::new(source.as_bytes())
+        .scan_tokens()
+        .expect("Benchmark does not scan.");
+
+    let scan = bench("scan", || {
+        for token in Scanner::new(source.as_bytes()) {
+            black_box(token.unwrap());
+        }
+    });
+    throughput(scan, source.len());
+    let parse = bench("parse", || {
+        let statements = Parser::new(tokens.iter().cloned().map(Ok))
+            .parse()
+            .expect("Benchmark does not parse.");
+        black_box(statements);
+    });
+    throughput(parse, source.len());
+    let both = bench("scan and parse", || {
+        let statements = Parser::new(Scanner::new(source.as_bytes()))
+            .parse()
+            .expect("Benchmark does not parse.");
+        black_box(statements);
+    });
+    throughput(both, source.len());
+}
@@ -0,0 +1,61 @@
+//! The classic Lox benchmarks, on both backends.
+//!
+//! Run with `cargo bench --bench scripts`, optionally followed by `-- name`
+//! to pick cases. Each case runs one script from `benches/lox` and reports
+//! how many times it ran per second. Scripts are parsed and compiled once;
+//! only running them is measured. Every script leaves its answer in the
+//! global `result`, which is checked before timing so that a script that
+//! fails can't pass for a fast one.
+//!
+//! Add `--features nan-boxing` to measure the 8-byte value representation
+//! instead of the default one.
+
+mod common;
+
+use common::bench;
+use rlox::symbol::Symbol;
+use rlox::{compiler, vm, Interpreter, Parser, Scanner};
+
+#[rustfmt::skip]
+const SCRIPTS: [(&str, &str, &str); 8] = [
+    ("fib", include_str!("lox/fib.lox"), "6765"),
+    ("binary_trees", include_str!("lox/binary_trees.lox"), "-674"),
+    ("equality", include_str!("lox/equality.lox"), "100000"),
+    ("string_equality", include_str!("lox/string_equality.lox"), "100000"),
+    ("zoo", include_str!("lox/zoo.lox"), "30000"),
+    ("method_call", include_str!("lox/method_call.lox"), "10000"),
+    ("instantiation", include_str!("lox/instantiation.lox"), "20000"),
+    ("trees", include_str!("lox/trees.lox"), "9750"),
+];
+
+fn result(interpreter: &Interpreter) -> String {
+    match interpreter.env.borrow().get_value(Symbol::intern("result")) {
+        Some(value) => value.to_string(),
+        None => String::from("no result"),
+    }
+}
+
+fn main() {
+    for (name, source, expected) in SCRIPTS {
+        let statements = Parser::new(Scanner::new(source.as_bytes()))
+            .parse()
+            .expect("Benchmark does not parse.");
+        let script = compiler::compile(&statements).expect("Benchmark does not compile.");
+
+        let run = || {
+            let mut interpreter = Interpreter::new();
+            interpreter.interpret(statements.clone());
+            interpreter
+        };
+        assert_eq!(result(&run()), expected, "{}", name);
+        bench(name, || drop(run()));
+
+        let run = || {
+            let mut interpreter = Interpreter::new();
+            vm::interpret_compiled(&mut interpreter, script.clone());
+            interpreter
+        };
+        assert_eq!(result(&run()), expected, "{} (vm)", name);
+        bench(&format!("{} (vm)", name), || drop(run()));
+    }
+}
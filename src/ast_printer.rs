@@ -1,40 +1,162 @@
 use crate::expr::*;
 use crate::scanner::Literal;
+use crate::stmt::{Function, Stmt};
 
 impl Expr {
     pub fn print(&self) -> String {
         match self {
-            Expr::Binary(expr) => parenthesize(&expr.operator.lexeme, &[&expr.left, &expr.right]),
+            Expr::Binary(expr) => {
+                parenthesize(expr.operator.lexeme.as_str(), &[&expr.left, &expr.right])
+            }
             Expr::Grouping(expr) => parenthesize(&String::from("group"), &[&expr.expression]),
             Expr::Literal(lit) => match lit {
-                Literal::Str(str) => {
-                    return str.to_string();
+                Literal::Str(str) => str.to_string(),
+                Literal::Number(num) => num.to_string(),
+                Literal::Identifier(identifier) => identifier.to_string(),
+                Literal::Bool(bool) => bool.to_string(),
+                Literal::Nil => String::from("nil"),
+            },
+            Expr::Unary(expr) => parenthesize(expr.operator.lexeme.as_str(), &[&expr.right]),
+            Expr::Variable(var) => var.name.lexeme.to_string(),
+            Expr::Assign(expr) => parenthesize(&format!("= {}", expr.name.lexeme), &[&expr.value]),
+            Expr::Logical(expr) => {
+                parenthesize(expr.operator.lexeme.as_str(), &[&expr.left, &expr.right])
+            }
+            Expr::Call(expr) => {
+                let exprs: Vec<&Expr> = std::iter::once(&expr.callee)
+                    .chain(expr.arguments.iter())
+                    .collect();
+                parenthesize("call", &exprs)
+            }
+            Expr::List(expr) => parenthesize(
+                &String::from("list"),
+                &expr.elements.iter().collect::<Vec<_>>(),
+            ),
+            Expr::Map(expr) => {
+                let exprs: Vec<&Expr> = expr.entries.iter().flat_map(|(k, v)| [k, v]).collect();
+                parenthesize(&String::from("map"), &exprs)
+            }
+            Expr::Index(expr) => parenthesize(&String::from("[]"), &[&expr.object, &expr.index]),
+            Expr::SetIndex(expr) => {
+                let name = match &expr.operator {
+                    Some(operator) => format!("[]{}=", operator.lexeme),
+                    None => String::from("[]="),
+                };
+                parenthesize(&name, &[&expr.object, &expr.index, &expr.value])
+            }
+            Expr::Function(expr) => print_function(expr),
+            Expr::Get(expr) => parenthesize(&format!(".{}", expr.name.lexeme), &[&expr.object]),
+            Expr::Conditional(expr) => parenthesize(
+                &String::from("?:"),
+                &[&expr.condition, &expr.then_branch, &expr.else_branch],
+            ),
+        }
+    }
+}
+
+impl Stmt {
+    pub fn print(&self) -> String {
+        match self {
+            Stmt::Block(stmt) => print_block("block", &stmt.statements),
+            Stmt::Expression(stmt) => parenthesize(";", &[&stmt.expression]),
+            Stmt::Print(stmt) => parenthesize("print", &[&stmt.expression]),
+            Stmt::Var(stmt) => match &stmt.initializer {
+                Some(initializer) => {
+                    parenthesize(&format!("var {} =", stmt.name.lexeme), &[initializer])
                 }
-                Literal::Number(num) => {
-                    return num.to_string();
+                None => format!("(var {})", stmt.name.lexeme),
+            },
+            Stmt::If(stmt) => match &stmt.else_branch {
+                Some(else_branch) => format!(
+                    "(if-else {} {} {})",
+                    stmt.condition.print(),
+                    stmt.then_branch.print(),
+                    else_branch.print()
+                ),
+                None => format!(
+                    "(if {} {})",
+                    stmt.condition.print(),
+                    stmt.then_branch.print()
+                ),
+            },
+            Stmt::While(stmt) => match &stmt.increment {
+                Some(increment) => format!(
+                    "(while {} {} {})",
+                    stmt.condition.print(),
+                    stmt.body.print(),
+                    increment.print()
+                ),
+                None => format!("(while {} {})", stmt.condition.print(), stmt.body.print()),
+            },
+            Stmt::ForIn(stmt) => format!(
+                "(for {} {} {})",
+                stmt.name.lexeme,
+                stmt.iterable.print(),
+                stmt.body.print()
+            ),
+            Stmt::Break(_) => String::from("(break)"),
+            Stmt::Continue(_) => String::from("(continue)"),
+            Stmt::Function(stmt) => print_function(stmt),
+            Stmt::Return(stmt) => match &stmt.value {
+                Some(value) => parenthesize("return", &[value]),
+                None => String::from("(return)"),
+            },
+            Stmt::Import(stmt) => match &stmt.name {
+                Some(name) => format!("(import {} as {})", stmt.path.lexeme, name.lexeme),
+                None => format!("(import {})", stmt.path.lexeme),
+            },
+            Stmt::Throw(stmt) => parenthesize("throw", &[&stmt.value]),
+            Stmt::Try(stmt) => {
+                let mut printed = format!("(try {}", print_block("block", &stmt.body));
+                if let Some(catch) = &stmt.catch {
+                    let name = format!("catch {}", catch.name.lexeme);
+                    printed.push(' ');
+                    printed.push_str(&print_block(&name, &catch.body));
                 }
-                Literal::Identifier(identifier) => {
-                    return identifier.to_string();
+                if let Some(finally) = &stmt.finally {
+                    printed.push(' ');
+                    printed.push_str(&print_block("finally", finally));
                 }
-            },
-            Expr::Unary(expr) => parenthesize(&expr.operator.lexeme, &[&expr.right]),
-            Expr::Variable(var) => var.name.to_string(),
-            Expr::Assign(_expr) => todo!(),
-            Expr::Logical(_) => todo!(),
-            Expr::Call(_) => todo!(),
+                printed.push(')');
+                printed
+            }
         }
     }
 }
 
-fn parenthesize(name: &String, exprs: &[&Expr]) -> String {
+/// Function declarations print their name after `fun`; expressions don't.
+fn print_function(function: &Function) -> String {
+    let parameters: Vec<&str> = function
+        .parameters
+        .iter()
+        .map(|p| p.lexeme.as_str())
+        .collect();
+    let name = match &function.name {
+        Some(name) => format!("fun {} ({})", name.lexeme, parameters.join(" ")),
+        None => format!("fun ({})", parameters.join(" ")),
+    };
+    print_block(&name, &function.body)
+}
+
+fn print_block(name: &str, statements: &[Stmt]) -> String {
+    let mut printed = format!("({}", name);
+    for stmt in statements {
+        printed.push(' ');
+        printed.push_str(&stmt.print());
+    }
+    printed.push(')');
+    printed
+}
+
+fn parenthesize(name: &str, exprs: &[&Expr]) -> String {
     let mut parenthesized: String = String::from("");
 
-    parenthesized.push_str("(");
+    parenthesized.push('(');
     parenthesized.push_str(name);
     for expr in exprs {
-        parenthesized.push_str(" ");
+        parenthesized.push(' ');
         parenthesized.push_str(&expr.print());
     }
-    parenthesized.push_str(")");
-    return parenthesized;
+    parenthesized.push(')');
+    parenthesized
 }
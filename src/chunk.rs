@@ -0,0 +1,153 @@
+use crate::scanner::Span;
+use crate::symbol::Symbol;
+use crate::value::Str;
+use std::rc::Rc;
+
+/// One instruction of the bytecode VM. Operands follow the opcode in the
+/// code stream: `u8` for slots, cells, upvalues, argument counts and binary
+/// operators, big-endian `u16` for constant indices, element counts and
+/// jump offsets.
+#[rustfmt::skip]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    // Constants and literals.
+    Constant, Nil, True, False, Pop,
+    // Variables.
+    GetLocal, SetLocal, GetGlobal, DefineGlobal, SetGlobal,
+    GetCell, SetCell, DefineCell, NewCell, GetUpvalue, SetUpvalue,
+    // Properties and indexing.
+    GetProperty, GetIndex, SetIndex, SetIndexOp,
+    // Operators.
+    Equal, NotEqual, Greater, GreaterEqual, Less, LessEqual,
+    Add, Subtract, Multiply, Divide, Modulo, Not, Negate,
+    // Statements and control flow.
+    Print, Jump, JumpIfFalse, Loop, Call, Closure, Return,
+    // Collections and iteration.
+    List, Map, IterStart, IterNext, IterEnd,
+    // Modules and exceptions.
+    Import, Throw, PushHandler, PopHandler,
+}
+
+#[rustfmt::skip]
+const OPCODES: [OpCode; 49] = [
+    OpCode::Constant, OpCode::Nil, OpCode::True, OpCode::False, OpCode::Pop,
+    OpCode::GetLocal, OpCode::SetLocal, OpCode::GetGlobal, OpCode::DefineGlobal, OpCode::SetGlobal,
+    OpCode::GetCell, OpCode::SetCell, OpCode::DefineCell, OpCode::NewCell, OpCode::GetUpvalue,
+    OpCode::SetUpvalue,
+    OpCode::GetProperty, OpCode::GetIndex, OpCode::SetIndex, OpCode::SetIndexOp,
+    OpCode::Equal, OpCode::NotEqual, OpCode::Greater, OpCode::GreaterEqual, OpCode::Less,
+    OpCode::LessEqual,
+    OpCode::Add, OpCode::Subtract, OpCode::Multiply, OpCode::Divide, OpCode::Modulo, OpCode::Not,
+    OpCode::Negate,
+    OpCode::Print, OpCode::Jump, OpCode::JumpIfFalse, OpCode::Loop, OpCode::Call, OpCode::Closure,
+    OpCode::Return,
+    OpCode::List, OpCode::Map, OpCode::IterStart, OpCode::IterNext, OpCode::IterEnd,
+    OpCode::Import, OpCode::Throw, OpCode::PushHandler, OpCode::PopHandler,
+];
+
+impl OpCode {
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OPCODES.get(byte as usize).copied()
+    }
+
+    /// Bytes of operands following the opcode.
+    pub fn operand_len(self) -> usize {
+        match self {
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetCell
+            | OpCode::SetCell
+            | OpCode::DefineCell
+            | OpCode::NewCell
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call
+            | OpCode::SetIndexOp => 1,
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::Closure
+            | OpCode::Import
+            | OpCode::List
+            | OpCode::Map
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Loop
+            | OpCode::IterNext => 2,
+            OpCode::PushHandler => 4,
+            _ => 0,
+        }
+    }
+}
+
+/// A value known at compile time.
+#[derive(Clone, Debug)]
+pub enum Constant {
+    Number(f64),
+    Str(Str),
+    /// A variable, property or module path.
+    Name(Symbol),
+    Function(Rc<Prototype>),
+}
+
+/// Where a closure finds a captured variable when it is created: in a cell
+/// of the enclosing call, or among the enclosing closure's own upvalues.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capture {
+    Cell(u8),
+    Upvalue(u8),
+}
+
+/// A compiled function body. Locals that some closure captures live in
+/// `cells` boxes instead of on the stack.
+#[derive(Debug)]
+pub struct Prototype {
+    /// `None` for function expressions and the top-level script.
+    pub name: Option<String>,
+    pub arity: usize,
+    pub cells: usize,
+    pub captures: Vec<Capture>,
+    pub chunk: Chunk,
+}
+
+/// Bytecode with its constant pool. `lines` holds the source line of every
+/// byte in `code`. Instructions whose errors name a token, such as the `]`
+/// of an index or the path of an import, find it in `spans`, keyed by the
+/// offset just past the instruction.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub lines: Vec<usize>,
+    pub spans: Vec<(usize, Span)>,
+    pub constants: Vec<Constant>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, line: usize) {
+        self.code.push(byte);
+        self.lines.push(line);
+    }
+
+    /// Records the token of the instruction just written.
+    pub fn add_span(&mut self, span: Span) {
+        self.spans.push((self.code.len(), span));
+    }
+
+    /// The token of the instruction that ends at `offset`.
+    pub fn span(&self, offset: usize) -> Option<Span> {
+        let index = self.spans.binary_search_by_key(&offset, |&(end, _)| end);
+        index.ok().map(|index| self.spans[index].1)
+    }
+
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+}
@@ -0,0 +1,836 @@
+use crate::{
+    chunk::{Capture, Chunk, Constant, OpCode, Prototype},
+    expr::Expr,
+    resolver,
+    scanner::{Literal, Span, TokenType},
+    stmt::{Catch, ForIn, Function, Import, Stmt, Try, While},
+    symbol::Symbol,
+};
+use std::{collections::HashMap, fmt, path::Path, rc::Rc};
+
+#[derive(Debug)]
+pub enum Error {
+    TooManyConstants(usize),
+    TooManyLocals(usize),
+    TooManyCells(usize),
+    TooManyUpvalues(usize),
+    TooManyArguments(usize),
+    TooManyElements(usize),
+    JumpTooLarge(usize),
+    /// The resolver didn't box a variable that a closure captures.
+    NotInCell(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (line, message) = match *self {
+            Error::TooManyConstants(line) => (line, "Too many constants in one function."),
+            Error::TooManyLocals(line) => (line, "Too many local variables in function."),
+            Error::TooManyCells(line) => (line, "Too many captured variables in function."),
+            Error::TooManyUpvalues(line) => (line, "Too many closure variables in function."),
+            Error::TooManyArguments(line) => (line, "Can't have more than 255 arguments."),
+            Error::TooManyElements(line) => (line, "Too many elements in one literal."),
+            Error::JumpTooLarge(line) => (line, "Too much code to jump over."),
+            Error::NotInCell(line) => (line, "Captured variable is not in a cell."),
+        };
+        write!(f, "[line: {}] Error: {}", line, message)
+    }
+}
+
+impl Error {
+    pub fn line(&self) -> usize {
+        match *self {
+            Error::TooManyConstants(line)
+            | Error::TooManyLocals(line)
+            | Error::TooManyCells(line)
+            | Error::TooManyUpvalues(line)
+            | Error::TooManyArguments(line)
+            | Error::TooManyElements(line)
+            | Error::JumpTooLarge(line)
+            | Error::NotInCell(line) => line,
+        }
+    }
+}
+
+/// Compiles a script or module. Its top-level declarations become globals of
+/// the environment the VM runs it in. The statements are resolved first, and
+/// the variables the resolver puts in cells are the ones compiled as cells.
+pub fn compile(statements: &[Stmt]) -> Result<Rc<Prototype>, Error> {
+    resolver::resolve(statements).map_err(|err| Error::NotInCell(err.line()))?;
+    let mut compiler = Compiler {
+        functions: vec![FunctionState::new(None, 0)],
+        line: 1,
+    };
+    for statement in statements {
+        compiler.statement(statement)?;
+    }
+    compiler.emit_op(OpCode::Nil);
+    compiler.emit_op(OpCode::Return);
+    Ok(Rc::new(compiler.functions.pop().unwrap().finish()))
+}
+
+/// Where a local variable lives at runtime.
+#[derive(Clone, Copy, Debug)]
+enum Slot {
+    Stack(u8),
+    Cell(u8),
+}
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    slot: Slot,
+}
+
+struct Loop {
+    /// Scope depth outside the loop body. Jumping out discards every local
+    /// declared deeper than this.
+    depth: usize,
+    /// Number of enclosing `try` blocks when the loop started.
+    tries: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+struct FunctionState<'a> {
+    name: Option<String>,
+    arity: usize,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    cells: usize,
+    captures: Vec<Capture>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    /// The `finally` blocks of the `try` statements whose handlers are
+    /// active, innermost last.
+    tries: Vec<Option<&'a [Stmt]>>,
+    identifiers: HashMap<Symbol, u16>,
+}
+
+impl<'a> FunctionState<'a> {
+    fn new(name: Option<String>, arity: usize) -> Self {
+        FunctionState {
+            name,
+            arity,
+            chunk: Chunk::default(),
+            // Slot zero holds the function being called.
+            locals: vec![Local {
+                name: Symbol::EMPTY,
+                depth: 0,
+                slot: Slot::Stack(0),
+            }],
+            cells: 0,
+            captures: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+            tries: Vec::new(),
+            identifiers: HashMap::new(),
+        }
+    }
+
+    fn finish(self) -> Prototype {
+        Prototype {
+            name: self.name,
+            arity: self.arity,
+            cells: self.cells,
+            captures: self.captures,
+            chunk: self.chunk,
+        }
+    }
+
+    fn stack_slots(&self) -> usize {
+        self.locals
+            .iter()
+            .filter(|local| matches!(local.slot, Slot::Stack(_)))
+            .count()
+    }
+
+    fn resolve_local(&self, name: Symbol) -> Option<Slot> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|local| local.name == name)
+            .map(|local| local.slot)
+    }
+}
+
+struct Compiler<'a> {
+    /// The function being compiled and the ones it is nested in, innermost
+    /// last.
+    functions: Vec<FunctionState<'a>>,
+    line: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn current(&mut self) -> &mut FunctionState<'a> {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn statement(&mut self, stmt: &'a Stmt) -> Result<(), Error> {
+        match stmt {
+            Stmt::Expression(stmt) => {
+                self.expression(&stmt.expression)?;
+                self.emit_op(OpCode::Pop);
+            }
+            Stmt::Print(stmt) => {
+                self.expression(&stmt.expression)?;
+                self.emit_op(OpCode::Print);
+            }
+            Stmt::Var(stmt) => {
+                self.line = stmt.name.line;
+                match &stmt.initializer {
+                    Some(initializer) => self.expression(initializer)?,
+                    None => self.emit_op(OpCode::Nil),
+                }
+                self.define_variable(stmt.name.lexeme, stmt.slot.get())?;
+            }
+            Stmt::Block(block) => self.block(&block.statements)?,
+            Stmt::If(stmt) => {
+                self.expression(&stmt.condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(&stmt.then_branch)?;
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump)?;
+                self.emit_op(OpCode::Pop);
+                if let Some(else_branch) = &stmt.else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump)?;
+            }
+            Stmt::While(stmt) => self.while_statement(stmt)?,
+            Stmt::ForIn(stmt) => self.for_in_statement(stmt)?,
+            Stmt::Break(stmt) => {
+                self.line = stmt.keyword.line;
+                let jump = self.exit_loop()?;
+                self.current().loops.last_mut().unwrap().breaks.push(jump);
+            }
+            Stmt::Continue(stmt) => {
+                self.line = stmt.keyword.line;
+                let jump = self.exit_loop()?;
+                self.current()
+                    .loops
+                    .last_mut()
+                    .unwrap()
+                    .continues
+                    .push(jump);
+            }
+            Stmt::Function(function) => self.function_declaration(function)?,
+            Stmt::Return(stmt) => {
+                self.line = stmt.keyword.line;
+                match &stmt.value {
+                    Some(value) => self.expression(value)?,
+                    None => self.emit_op(OpCode::Nil),
+                }
+                if !self.current().tries.is_empty() {
+                    // Keep the return value as a hidden local while the
+                    // `finally` blocks run.
+                    self.add_local(Symbol::EMPTY, Slot::Stack(0))?;
+                    self.exit_tries(0)?;
+                    self.current().locals.pop();
+                }
+                self.emit_op(OpCode::Return);
+            }
+            Stmt::Import(stmt) => self.import(stmt)?,
+            Stmt::Throw(stmt) => {
+                self.expression(&stmt.value)?;
+                self.line = stmt.keyword.line;
+                self.emit_op(OpCode::Throw);
+            }
+            Stmt::Try(stmt) => self.try_statement(stmt)?,
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &'a [Stmt]) -> Result<(), Error> {
+        self.begin_scope();
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn while_statement(&mut self, stmt: &'a While) -> Result<(), Error> {
+        let start = self.current().chunk.code.len();
+        self.expression(&stmt.condition)?;
+        let exit = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+
+        let depth = self.current().scope_depth;
+        self.begin_loop(depth);
+        self.statement(&stmt.body)?;
+        let body = self.current().loops.pop().unwrap();
+        for jump in body.continues {
+            self.patch_jump(jump)?;
+        }
+        if let Some(increment) = &stmt.increment {
+            self.expression(increment)?;
+            self.emit_op(OpCode::Pop);
+        }
+        self.emit_loop(start)?;
+
+        self.patch_jump(exit)?;
+        self.emit_op(OpCode::Pop);
+        for jump in body.breaks {
+            self.patch_jump(jump)?;
+        }
+        Ok(())
+    }
+
+    /// The iterator lives on the VM's iterator stack rather than in a local,
+    /// so the loop variable is the only local the loop declares.
+    fn for_in_statement(&mut self, stmt: &'a ForIn) -> Result<(), Error> {
+        self.expression(&stmt.iterable)?;
+        self.line = stmt.keyword.line;
+        self.emit_op(OpCode::IterStart);
+        let start = self.current().chunk.code.len();
+        let exit = self.emit_jump(OpCode::IterNext);
+
+        let depth = self.current().scope_depth;
+        self.begin_loop(depth);
+        self.begin_scope();
+        self.define_variable(stmt.name.lexeme, stmt.slot.get())?;
+        self.statement(&stmt.body)?;
+        self.end_scope();
+        let body = self.current().loops.pop().unwrap();
+        for jump in body.continues {
+            self.patch_jump(jump)?;
+        }
+        self.emit_loop(start)?;
+
+        self.patch_jump(exit)?;
+        for jump in body.breaks {
+            self.patch_jump(jump)?;
+        }
+        self.emit_op(OpCode::IterEnd);
+        Ok(())
+    }
+
+    fn begin_loop(&mut self, depth: usize) {
+        let tries = self.current().tries.len();
+        self.current().loops.push(Loop {
+            depth,
+            tries,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+    }
+
+    /// Leaves the `try` blocks and scopes inside the innermost loop and
+    /// emits a jump for the caller to patch.
+    fn exit_loop(&mut self) -> Result<usize, Error> {
+        let (depth, tries) = {
+            let innermost = self.current().loops.last().unwrap();
+            (innermost.depth, innermost.tries)
+        };
+        self.exit_tries(tries)?;
+        let pops = self
+            .current()
+            .locals
+            .iter()
+            .filter(|local| local.depth > depth && matches!(local.slot, Slot::Stack(_)))
+            .count();
+        for _ in 0..pops {
+            self.emit_op(OpCode::Pop);
+        }
+        Ok(self.emit_jump(OpCode::Jump))
+    }
+
+    /// Pops the handlers of the `try` blocks nested deeper than `tries`,
+    /// running their `finally` blocks inline, innermost first.
+    fn exit_tries(&mut self, tries: usize) -> Result<(), Error> {
+        for i in (tries..self.current().tries.len()).rev() {
+            self.emit_op(OpCode::PopHandler);
+            let outer = self.current().tries.split_off(i);
+            if let Some(finally) = outer[0] {
+                self.block(finally)?;
+            }
+            self.current().tries.extend(outer);
+        }
+        Ok(())
+    }
+
+    /// The catch handler protects the body. With a `finally` block, a second
+    /// handler protects the `catch` block, and any error that escapes runs
+    /// `finally` before being thrown again.
+    fn try_statement(&mut self, stmt: &'a Try) -> Result<(), Error> {
+        let finally = stmt.finally.as_deref();
+        let handler = self.emit_handler()?;
+        self.current().tries.push(finally);
+        self.block(&stmt.body)?;
+        self.current().tries.pop();
+        self.emit_op(OpCode::PopHandler);
+        let mut exits = vec![self.emit_jump(OpCode::Jump)];
+
+        // The handlers leave the error value on top of the stack.
+        self.patch_jump(handler)?;
+        if let Some(Catch { name, body, slot }) = &stmt.catch {
+            let rethrow = match finally {
+                Some(_) => Some(self.emit_handler()?),
+                None => None,
+            };
+            self.current().tries.push(finally);
+            self.begin_scope();
+            self.define_variable(name.lexeme, slot.get())?;
+            for statement in body {
+                self.statement(statement)?;
+            }
+            self.end_scope();
+            self.current().tries.pop();
+            if let Some(rethrow) = rethrow {
+                self.emit_op(OpCode::PopHandler);
+                exits.push(self.emit_jump(OpCode::Jump));
+                self.patch_jump(rethrow)?;
+            }
+        }
+        if let Some(finally) = finally {
+            self.begin_scope();
+            self.add_local(Symbol::EMPTY, Slot::Stack(0))?;
+            self.block(finally)?;
+            self.emit_op(OpCode::Throw);
+            // `Throw` consumed the error value, so the scope ends without
+            // popping it.
+            self.current().locals.pop();
+            self.current().scope_depth -= 1;
+        }
+
+        for exit in exits {
+            self.patch_jump(exit)?;
+        }
+        if let Some(finally) = finally {
+            self.block(finally)?;
+        }
+        Ok(())
+    }
+
+    /// `PushHandler` records the stack height of the enclosing statement so
+    /// the VM can unwind to it.
+    fn emit_handler(&mut self) -> Result<usize, Error> {
+        let jump = self.emit_jump(OpCode::PushHandler);
+        let slots = self.current().stack_slots();
+        self.emit_u16(slots as u16);
+        Ok(jump)
+    }
+
+    fn function_declaration(&mut self, function: &'a Rc<Function>) -> Result<(), Error> {
+        let name = function.name.as_ref().unwrap();
+        self.line = name.line;
+        if self.current().scope_depth == 0 {
+            self.function(function)?;
+            let constant = self.identifier(name.lexeme)?;
+            self.emit_op(OpCode::DefineGlobal);
+            self.emit_u16(constant);
+        } else if is_cell(function.slot.get()) {
+            // The cell exists before the closure so that the function can
+            // refer to itself.
+            let cell = self.add_cell()?;
+            self.emit_op(OpCode::NewCell);
+            self.emit_byte(cell);
+            self.add_local(name.lexeme, Slot::Cell(cell))?;
+            self.function(function)?;
+            self.emit_op(OpCode::SetCell);
+            self.emit_byte(cell);
+            self.emit_op(OpCode::Pop);
+        } else {
+            let slot = self.next_slot()?;
+            self.add_local(name.lexeme, Slot::Stack(slot))?;
+            self.function(function)?;
+        }
+        Ok(())
+    }
+
+    /// Compiles `function` into its own prototype and emits the `Closure`
+    /// instruction that creates it at runtime.
+    fn function(&mut self, function: &'a Function) -> Result<(), Error> {
+        let name = function.name.as_ref().map(|name| name.lexeme.to_string());
+        self.functions
+            .push(FunctionState::new(name, function.parameters.len()));
+        self.begin_scope();
+        let layout = function.layout.borrow();
+        for (parameter, resolved) in function.parameters.iter().zip(&layout.parameters) {
+            let slot = self.next_slot()?;
+            if is_cell(*resolved) {
+                self.add_local(Symbol::EMPTY, Slot::Stack(slot))?;
+                self.emit_op(OpCode::GetLocal);
+                self.emit_byte(slot);
+                let cell = self.add_cell()?;
+                self.emit_op(OpCode::DefineCell);
+                self.emit_byte(cell);
+                self.add_local(parameter.lexeme, Slot::Cell(cell))?;
+            } else {
+                self.add_local(parameter.lexeme, Slot::Stack(slot))?;
+            }
+        }
+        for statement in &function.body {
+            self.statement(statement)?;
+        }
+        self.emit_op(OpCode::Nil);
+        self.emit_op(OpCode::Return);
+
+        let prototype = self.functions.pop().unwrap().finish();
+        let constant = self.make_constant(Constant::Function(Rc::new(prototype)))?;
+        self.emit_op(OpCode::Closure);
+        self.emit_u16(constant);
+        Ok(())
+    }
+
+    fn import(&mut self, stmt: &'a Import) -> Result<(), Error> {
+        self.line = stmt.keyword.line;
+        let path = match stmt.path.literal() {
+            Literal::Str(path) => Symbol::intern(&path),
+            _ => Symbol::EMPTY,
+        };
+        let constant = self.make_constant(Constant::Name(path))?;
+        self.emit_op(OpCode::Import);
+        self.emit_u16(constant);
+        self.current().chunk.add_span(stmt.path);
+        self.define_variable(import_binding(stmt), stmt.slot.get())
+    }
+
+    /// Binds the value on top of the stack to a new variable: a global at
+    /// the top level, otherwise a stack slot, or a cell if the resolver put
+    /// it in one.
+    fn define_variable(&mut self, name: Symbol, resolved: resolver::Slot) -> Result<(), Error> {
+        if self.current().scope_depth == 0 {
+            let constant = self.identifier(name)?;
+            self.emit_op(OpCode::DefineGlobal);
+            self.emit_u16(constant);
+        } else if is_cell(resolved) {
+            let cell = self.add_cell()?;
+            self.emit_op(OpCode::DefineCell);
+            self.emit_byte(cell);
+            self.add_local(name, Slot::Cell(cell))?;
+        } else {
+            let slot = self.next_slot()?;
+            self.add_local(name, Slot::Stack(slot))?;
+        }
+        Ok(())
+    }
+
+    /// A local's stack slot is the next free one, so `Slot::Stack(0)` passed
+    /// for hidden locals is replaced here.
+    fn add_local(&mut self, name: Symbol, slot: Slot) -> Result<(), Error> {
+        let slot = match slot {
+            Slot::Stack(_) => Slot::Stack(self.next_slot()?),
+            cell => cell,
+        };
+        let depth = self.current().scope_depth;
+        self.current().locals.push(Local { name, depth, slot });
+        Ok(())
+    }
+
+    fn next_slot(&mut self) -> Result<u8, Error> {
+        let slots = self.current().stack_slots();
+        u8::try_from(slots).map_err(|_| Error::TooManyLocals(self.line))
+    }
+
+    fn add_cell(&mut self) -> Result<u8, Error> {
+        let cell =
+            u8::try_from(self.current().cells).map_err(|_| Error::TooManyCells(self.line))?;
+        self.current().cells += 1;
+        Ok(cell)
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        let mut pops = 0;
+        while let Some(local) = state.locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            if let Slot::Stack(_) = local.slot {
+                pops += 1;
+            }
+            state.locals.pop();
+        }
+        for _ in 0..pops {
+            self.emit_op(OpCode::Pop);
+        }
+    }
+
+    fn expression(&mut self, expr: &'a Expr) -> Result<(), Error> {
+        match expr {
+            Expr::Literal(literal) => match literal {
+                Literal::Number(num) => self.emit_constant(Constant::Number(*num))?,
+                Literal::Str(str) => self.emit_constant(Constant::Str(str.clone()))?,
+                Literal::Bool(true) => self.emit_op(OpCode::True),
+                Literal::Bool(false) => self.emit_op(OpCode::False),
+                Literal::Nil | Literal::Identifier(_) => self.emit_op(OpCode::Nil),
+            },
+            Expr::Grouping(expr) => self.expression(&expr.expression)?,
+            Expr::Unary(expr) => {
+                self.expression(&expr.right)?;
+                self.line = expr.operator.line;
+                match expr.operator.ttype {
+                    TokenType::Minus => self.emit_op(OpCode::Negate),
+                    _ => self.emit_op(OpCode::Not),
+                }
+            }
+            Expr::Binary(expr) => {
+                self.expression(&expr.left)?;
+                self.expression(&expr.right)?;
+                self.line = expr.operator.line;
+                self.emit_op(binary_opcode(expr.operator.ttype));
+            }
+            Expr::Variable(expr) => self.variable(&expr.name, false)?,
+            Expr::Assign(expr) => {
+                self.expression(&expr.value)?;
+                self.variable(&expr.name, true)?;
+            }
+            Expr::Logical(expr) => {
+                self.expression(&expr.left)?;
+                self.line = expr.operator.line;
+                if expr.operator.ttype == TokenType::Or {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump)?;
+                    self.emit_op(OpCode::Pop);
+                    self.expression(&expr.right)?;
+                    self.patch_jump(end_jump)?;
+                } else {
+                    let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    self.emit_op(OpCode::Pop);
+                    self.expression(&expr.right)?;
+                    self.patch_jump(end_jump)?;
+                }
+            }
+            Expr::Conditional(expr) => {
+                self.expression(&expr.condition)?;
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.expression(&expr.then_branch)?;
+                let end_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(else_jump)?;
+                self.emit_op(OpCode::Pop);
+                self.expression(&expr.else_branch)?;
+                self.patch_jump(end_jump)?;
+            }
+            Expr::Call(expr) => {
+                self.expression(&expr.callee)?;
+                for argument in &expr.arguments {
+                    self.expression(argument)?;
+                }
+                self.line = expr.paren.line;
+                let count = u8::try_from(expr.arguments.len())
+                    .map_err(|_| Error::TooManyArguments(self.line))?;
+                self.emit_op(OpCode::Call);
+                self.emit_byte(count);
+            }
+            Expr::List(expr) => {
+                for element in &expr.elements {
+                    self.expression(element)?;
+                }
+                self.line = expr.bracket.line;
+                self.emit_op(OpCode::List);
+                self.emit_count(expr.elements.len())?;
+            }
+            Expr::Map(expr) => {
+                for (key, value) in &expr.entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.line = expr.brace.line;
+                self.emit_op(OpCode::Map);
+                self.emit_count(expr.entries.len())?;
+                self.current().chunk.add_span(expr.brace);
+            }
+            Expr::Index(expr) => {
+                self.expression(&expr.object)?;
+                self.expression(&expr.index)?;
+                self.line = expr.bracket.line;
+                self.emit_op(OpCode::GetIndex);
+                self.current().chunk.add_span(expr.bracket);
+            }
+            Expr::SetIndex(expr) => {
+                self.expression(&expr.object)?;
+                self.expression(&expr.index)?;
+                self.expression(&expr.value)?;
+                self.line = expr.bracket.line;
+                match &expr.operator {
+                    Some(operator) => {
+                        self.emit_op(OpCode::SetIndexOp);
+                        self.emit_byte(binary_opcode(operator.ttype) as u8);
+                    }
+                    None => self.emit_op(OpCode::SetIndex),
+                }
+                self.current().chunk.add_span(expr.bracket);
+            }
+            Expr::Get(expr) => {
+                self.expression(&expr.object)?;
+                self.line = expr.name.line;
+                let constant = self.identifier(expr.name.lexeme)?;
+                self.emit_op(OpCode::GetProperty);
+                self.emit_u16(constant);
+            }
+            Expr::Function(function) => self.function(function)?,
+        }
+        Ok(())
+    }
+
+    /// Emits a read, or a write of the value on top of the stack, of the
+    /// variable `name`: a local of this function, a variable captured from
+    /// an enclosing one, or a global.
+    fn variable(&mut self, name: &Span, assign: bool) -> Result<(), Error> {
+        self.line = name.line;
+        let top = self.functions.len() - 1;
+        let (op, operand) = if let Some(slot) = self.functions[top].resolve_local(name.lexeme) {
+            match (slot, assign) {
+                (Slot::Stack(slot), false) => (OpCode::GetLocal, slot as u16),
+                (Slot::Stack(slot), true) => (OpCode::SetLocal, slot as u16),
+                (Slot::Cell(cell), false) => (OpCode::GetCell, cell as u16),
+                (Slot::Cell(cell), true) => (OpCode::SetCell, cell as u16),
+            }
+        } else if let Some(upvalue) = self.resolve_upvalue(top, name.lexeme)? {
+            match assign {
+                false => (OpCode::GetUpvalue, upvalue as u16),
+                true => (OpCode::SetUpvalue, upvalue as u16),
+            }
+        } else {
+            let constant = self.identifier(name.lexeme)?;
+            match assign {
+                false => (OpCode::GetGlobal, constant),
+                true => (OpCode::SetGlobal, constant),
+            }
+        };
+        self.emit_op(op);
+        match op {
+            OpCode::GetGlobal | OpCode::SetGlobal => self.emit_u16(operand),
+            _ => self.emit_byte(operand as u8),
+        }
+        Ok(())
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: Symbol) -> Result<Option<u8>, Error> {
+        if function == 0 {
+            return Ok(None);
+        }
+        let enclosing = function - 1;
+        let capture = match self.functions[enclosing].resolve_local(name) {
+            Some(Slot::Cell(cell)) => Capture::Cell(cell),
+            Some(Slot::Stack(_)) => return Err(Error::NotInCell(self.line)),
+            None => match self.resolve_upvalue(enclosing, name)? {
+                Some(upvalue) => Capture::Upvalue(upvalue),
+                None => return Ok(None),
+            },
+        };
+        let captures = &mut self.functions[function].captures;
+        if let Some(index) = captures.iter().position(|c| *c == capture) {
+            return Ok(Some(index as u8));
+        }
+        let index = u8::try_from(captures.len()).map_err(|_| Error::TooManyUpvalues(self.line))?;
+        captures.push(capture);
+        Ok(Some(index))
+    }
+
+    /// Identifier constants are shared within a function.
+    fn identifier(&mut self, name: Symbol) -> Result<u16, Error> {
+        if let Some(constant) = self.current().identifiers.get(&name) {
+            return Ok(*constant);
+        }
+        let constant = self.make_constant(Constant::Name(name))?;
+        self.current().identifiers.insert(name, constant);
+        Ok(constant)
+    }
+
+    fn make_constant(&mut self, constant: Constant) -> Result<u16, Error> {
+        let index = self.current().chunk.add_constant(constant);
+        u16::try_from(index).map_err(|_| Error::TooManyConstants(self.line))
+    }
+
+    fn emit_constant(&mut self, constant: Constant) -> Result<(), Error> {
+        let index = self.make_constant(constant)?;
+        self.emit_op(OpCode::Constant);
+        self.emit_u16(index);
+        Ok(())
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.line;
+        self.current().chunk.write(byte, line);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.emit_byte(high);
+        self.emit_byte(low);
+    }
+
+    fn emit_count(&mut self, count: usize) -> Result<(), Error> {
+        let count = u16::try_from(count).map_err(|_| Error::TooManyElements(self.line))?;
+        self.emit_u16(count);
+        Ok(())
+    }
+
+    /// Emits a forward jump with a placeholder offset and returns the
+    /// position of the offset for `patch_jump`.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_u16(u16::MAX);
+        self.current().chunk.code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) -> Result<(), Error> {
+        let jump = self.current().chunk.code.len() - offset - 2;
+        let jump = u16::try_from(jump).map_err(|_| Error::JumpTooLarge(self.line))?;
+        let code = &mut self.current().chunk.code;
+        code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, start: usize) -> Result<(), Error> {
+        self.emit_op(OpCode::Loop);
+        let jump = self.current().chunk.code.len() - start + 2;
+        let jump = u16::try_from(jump).map_err(|_| Error::JumpTooLarge(self.line))?;
+        self.emit_u16(jump);
+        Ok(())
+    }
+}
+
+fn binary_opcode(ttype: TokenType) -> OpCode {
+    match ttype {
+        TokenType::Plus => OpCode::Add,
+        TokenType::Minus => OpCode::Subtract,
+        TokenType::Star => OpCode::Multiply,
+        TokenType::Slash => OpCode::Divide,
+        TokenType::Percent => OpCode::Modulo,
+        TokenType::Greater => OpCode::Greater,
+        TokenType::GreaterEqual => OpCode::GreaterEqual,
+        TokenType::Less => OpCode::Less,
+        TokenType::LessEqual => OpCode::LessEqual,
+        TokenType::BangEqual => OpCode::NotEqual,
+        _ => OpCode::Equal,
+    }
+}
+
+fn is_cell(slot: resolver::Slot) -> bool {
+    matches!(slot, resolver::Slot::Cell(_))
+}
+
+/// The variable an import binds. Without `as`, it is named after the file
+/// name without its extension.
+pub(crate) fn import_binding(stmt: &Import) -> Symbol {
+    match &stmt.name {
+        Some(name) => name.lexeme,
+        None => {
+            let stem = match stmt.path.literal() {
+                Literal::Str(path) => Path::new(&*path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned()),
+                _ => None,
+            };
+            let stem = stem.unwrap_or_default();
+            Symbol::intern(&stem)
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::chunk::{Chunk, Constant, OpCode, Prototype};
+use std::fmt::Write;
+
+/// Renders a compiled script and every function nested in it, one
+/// instruction per line:
+///
+/// ```text
+/// == <script> ==
+/// 0000    1 Constant            0 '1'
+/// 0003    | DefineGlobal        1 "x"
+/// 0006    2 GetGlobal           1 "x"
+/// 0009    | JumpIfFalse         8 -> 20
+/// ```
+///
+/// A `|` in the line column means the same line as the instruction above.
+pub fn disassemble(prototype: &Prototype) -> String {
+    let mut out = String::new();
+    disassemble_prototype(&mut out, prototype, "<script>");
+    out
+}
+
+fn disassemble_prototype(out: &mut String, prototype: &Prototype, name: &str) {
+    writeln!(out, "== {} ==", name).unwrap();
+    disassemble_chunk(out, &prototype.chunk);
+
+    for constant in &prototype.chunk.constants {
+        if let Constant::Function(function) = constant {
+            writeln!(out).unwrap();
+            disassemble_prototype(out, function, &constant_text(constant));
+        }
+    }
+}
+
+fn disassemble_chunk(out: &mut String, chunk: &Chunk) {
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(out, chunk, offset);
+    }
+}
+
+/// Writes the instruction at `offset` and returns the offset of the next one.
+pub fn disassemble_instruction(out: &mut String, chunk: &Chunk, offset: usize) -> usize {
+    write!(out, "{:04} ", offset).unwrap();
+    if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
+        write!(out, "   | ").unwrap();
+    } else {
+        write!(out, "{:4} ", chunk.lines[offset]).unwrap();
+    }
+
+    let op = match OpCode::from_byte(chunk.code[offset]) {
+        Some(op) => op,
+        None => {
+            writeln!(out, "Unknown opcode {}", chunk.code[offset]).unwrap();
+            return offset + 1;
+        }
+    };
+    let name = format!("{:?}", op);
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::Closure
+        | OpCode::Import => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = &chunk.constants[index as usize];
+            writeln!(out, "{:<16} {:4} {}", name, index, constant_text(constant)).unwrap();
+            offset + 3
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetCell
+        | OpCode::SetCell
+        | OpCode::DefineCell
+        | OpCode::NewCell
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => {
+            writeln!(out, "{:<16} {:4}", name, chunk.code[offset + 1]).unwrap();
+            offset + 2
+        }
+        OpCode::SetIndexOp => {
+            match OpCode::from_byte(chunk.code[offset + 1]) {
+                Some(operator) => writeln!(out, "{:<16} {:?}", name, operator).unwrap(),
+                None => writeln!(out, "{:<16} ?{}", name, chunk.code[offset + 1]).unwrap(),
+            }
+            offset + 2
+        }
+        OpCode::List | OpCode::Map => {
+            writeln!(out, "{:<16} {:4}", name, chunk.read_u16(offset + 1)).unwrap();
+            offset + 3
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::IterNext => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            let target = offset + 3 + jump;
+            writeln!(out, "{:<16} {:4} -> {}", name, jump, target).unwrap();
+            offset + 3
+        }
+        OpCode::Loop => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            let target = (offset + 3).saturating_sub(jump);
+            writeln!(out, "{:<16} {:4} -> {}", name, jump, target).unwrap();
+            offset + 3
+        }
+        OpCode::PushHandler => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            let slots = chunk.read_u16(offset + 3);
+            let target = offset + 3 + jump;
+            writeln!(
+                out,
+                "{:<16} {:4} -> {} (slots {})",
+                name, jump, target, slots
+            )
+            .unwrap();
+            offset + 5
+        }
+        _ => {
+            writeln!(out, "{}", name).unwrap();
+            offset + 1
+        }
+    }
+}
+
+fn constant_text(constant: &Constant) -> String {
+    match constant {
+        Constant::Number(num) => format!("'{}'", num),
+        Constant::Str(str) => format!("{:?}", str),
+        Constant::Name(name) => format!("{:?}", name),
+        Constant::Function(function) => match &function.name {
+            Some(name) => format!("<fn {}>", name),
+            None => String::from("<fn>"),
+        },
+    }
+}
@@ -1,10 +1,15 @@
-use crate::{interpreter::Data, scanner::Token};
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use crate::{
+    interpreter::Data,
+    scanner::Span,
+    symbol::{Symbol, SymbolMap},
+    value::Value,
+};
+use std::{cell::RefCell, rc::Rc};
 
 #[derive(Clone, Debug)]
 pub struct Environment {
     pub enclosing: Option<Rc<RefCell<Environment>>>,
-    pub values: HashMap<String, Data>,
+    pub values: SymbolMap<Value>,
 }
 
 #[derive(Debug)]
@@ -21,39 +26,41 @@ impl std::fmt::Display for Error {
 }
 
 impl Environment {
-    pub fn define(&mut self, name: String, value: Data) {
-        self.values.insert(name, value);
+    pub fn define(&mut self, name: Symbol, value: Data) {
+        self.values.insert(name, value.into());
     }
 
-    pub fn get(&mut self, name: &Token) -> Result<Data, Error> {
-        match self.values.get(&name.lexeme) {
-            Some(val) => return Ok(val.clone()),
-            _ => (),
-        };
-
-        if self.enclosing.is_some() {
-            return match self.enclosing.as_mut().unwrap().borrow_mut().get(&name) {
-                Ok(val) => Ok(val.clone()),
-                Err(_) => Err(Error::Undefined(name.lexeme.clone())),
-            };
-        };
-        Err(Error::Undefined(name.lexeme.clone()))
+    pub fn get(&mut self, name: &Span) -> Result<Data, Error> {
+        self.get_value(name.lexeme)
+            .ok_or_else(|| Error::Undefined(name.lexeme.to_string()))
     }
 
-    pub fn assign(&mut self, name: &Token, value: &Data) {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme.clone(), value.clone());
-            return;
+    /// Looks `name` up in this scope and then in the enclosing ones.
+    pub fn get_value(&self, name: Symbol) -> Option<Data> {
+        match self.values.get(&name) {
+            Some(value) => Some(value.get()),
+            None => self.enclosing.as_ref()?.borrow().get_value(name),
         }
+    }
+
+    pub fn assign(&mut self, name: &Span, value: &Data) -> Result<(), Error> {
+        if self.assign_value(name.lexeme, value.clone()) {
+            Ok(())
+        } else {
+            Err(Error::Undefined(name.lexeme.to_string()))
+        }
+    }
 
-        if self.enclosing.is_some() {
-            self.enclosing
-                .as_mut()
-                .unwrap()
-                .borrow_mut()
-                .assign(&name, &value);
-            return;
-        };
-        Error::Undefined(name.lexeme.clone());
+    /// Updates the innermost definition of `name`. Returns false if it isn't
+    /// defined anywhere.
+    pub fn assign_value(&mut self, name: Symbol, value: Data) -> bool {
+        if let Some(slot) = self.values.get_mut(&name) {
+            *slot = value.into();
+            return true;
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_value(name, value),
+            None => false,
+        }
     }
 }
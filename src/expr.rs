@@ -1,5 +1,9 @@
+use crate::resolver::Slot;
 use crate::scanner::Literal;
-use crate::scanner::Token;
+use crate::scanner::Span;
+use crate::stmt::Function;
+use std::cell::Cell;
+use std::rc::Rc;
 
 #[derive(Clone, Debug)]
 pub enum Expr {
@@ -11,12 +15,21 @@ pub enum Expr {
     Assign(Box<Assign>),
     Logical(Box<Logical>), 
     Call(Box<Call>),
+    Conditional(Box<Conditional>),
+    List(Box<List>),
+    Map(Box<Map>),
+    Index(Box<Index>),
+    SetIndex(Box<SetIndex>),
+    Get(Box<Get>),
+    /// `fun (parameters) { body }`, evaluating to the same kind of function
+    /// value as a declaration.
+    Function(Rc<Function>),
 }
 
 #[derive(Clone, Debug)]
 pub struct Binary {
     pub left: Expr,
-    pub operator: Token,
+    pub operator: Span,
     pub right: Expr,
 }
 
@@ -27,31 +40,78 @@ pub struct Grouping {
 
 #[derive(Clone, Debug)]
 pub struct Unary {
-    pub operator: Token,
+    pub operator: Span,
     pub right: Expr,
 }
 
 #[derive(Clone, Debug)]
 pub struct Variable {
-    pub name: Token,
+    pub name: Span,
+    /// Set by the resolver.
+    pub slot: Cell<Slot>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Assign {
-    pub name: Token,
+    pub name: Span,
     pub value: Expr,
+    pub slot: Cell<Slot>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Logical {
     pub left: Expr,
-    pub operator: Token,
+    pub operator: Span,
     pub right: Expr,
 }
 
 #[derive(Clone, Debug)]
 pub struct Call {
     pub callee: Expr,
-    pub paren: Token,
+    pub paren: Span,
     pub arguments: Vec<Expr>,
 }
+
+#[derive(Clone, Debug)]
+pub struct Conditional {
+    pub condition: Expr,
+    pub then_branch: Expr,
+    pub else_branch: Expr,
+}
+
+#[derive(Clone, Debug)]
+pub struct List {
+    pub bracket: Span,
+    pub elements: Vec<Expr>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Map {
+    pub brace: Span,
+    pub entries: Vec<(Expr, Expr)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Index {
+    pub object: Expr,
+    pub bracket: Span,
+    pub index: Expr,
+}
+
+/// `object[index] = value`. `operator` holds the binary operator of a
+/// compound assignment such as `xs[i] += 1`.
+#[derive(Clone, Debug)]
+pub struct SetIndex {
+    pub object: Expr,
+    pub bracket: Span,
+    pub index: Expr,
+    pub operator: Option<Span>,
+    pub value: Expr,
+}
+
+/// `object.name`
+#[derive(Clone, Debug)]
+pub struct Get {
+    pub object: Expr,
+    pub name: Span,
+}
@@ -0,0 +1,56 @@
+use crate::{
+    environment::Environment,
+    interpreter::{Data, Error, Interpreter, LoxCallable},
+    scanner::Span,
+    stmt,
+    value::Value,
+};
+use std::{cell::RefCell, fmt, ops::RangeInclusive, rc::Rc};
+
+/// A function declared in Lox, by a `fun` declaration or a function
+/// expression, together with the scope it was created in.
+pub struct LoxFunction {
+    pub declaration: Rc<stmt::Function>,
+    /// Where the function's globals are looked up.
+    pub closure: Rc<RefCell<Environment>>,
+    /// The local variables of enclosing calls that the function refers to.
+    pub upvalues: Rc<[Rc<RefCell<Value>>]>,
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.declaration.name {
+            Some(name) => write!(f, "<fn {}>", name.lexeme),
+            None => write!(f, "<fn>"),
+        }
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> RangeInclusive<usize> {
+        let arity = self.declaration.parameters.len();
+        arity..=arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _paren: &Span,
+        arguments: Vec<Data>,
+    ) -> Result<Data, Error> {
+        let layout = self.declaration.layout.borrow();
+        interpreter.push_frame(&layout, self.upvalues.clone());
+        let parameters = self.declaration.parameters.iter().zip(&layout.parameters);
+        for ((parameter, slot), argument) in parameters.zip(arguments) {
+            interpreter.define(*slot, parameter.lexeme, argument);
+        }
+        drop(layout);
+        let result = interpreter.execute_in(&self.declaration.body, self.closure.clone());
+        interpreter.pop_frame();
+        match result {
+            Ok(()) => Ok(Data::Nil),
+            Err(Error::Return(value)) => Ok(value),
+            Err(err) => Err(err),
+        }
+    }
+}
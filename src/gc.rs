@@ -0,0 +1,221 @@
+//! The garbage collector.
+//!
+//! Every object a program creates while it runs — strings, lists, maps,
+//! functions, closures, captured variables, environments, modules and
+//! caught errors — is handed to the `Heap`, which owns it from then on.
+//! `collect` marks everything reachable from the roots the interpreter
+//! passes in and sweeps the rest: unreachable objects are emptied, which
+//! breaks any cycles between them, and released.
+//!
+//! Rust code also holds objects while it works, such as the left operand of
+//! `+` while the right one is evaluated. A collection therefore never runs
+//! in the middle of an allocation. Allocating only makes a collection due,
+//! and the interpreter runs it at the next safe point, where every such
+//! value has been put where the roots reach it.
+//!
+//! String literals and builtins are part of the program rather than the
+//! heap and live as long as it does.
+
+use crate::{
+    environment::Environment,
+    function::LoxFunction,
+    interpreter::{Data, ErrorObject},
+    map::LoxMap,
+    module::Module,
+    value::{Str, Value},
+    vm::Closure,
+};
+use std::{cell::RefCell, collections::HashSet, fmt, rc::Rc};
+
+/// Collections wait until at least this many objects are on the heap.
+const MIN_THRESHOLD: usize = 1024;
+
+#[derive(Clone)]
+pub enum Object {
+    Str(Str),
+    Env(Rc<RefCell<Environment>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    /// A captured local variable.
+    Cell(Rc<RefCell<Value>>),
+    Function(Rc<LoxFunction>),
+    Closure(Rc<Closure>),
+    Module(Rc<Module>),
+    Error(Rc<ErrorObject>),
+}
+
+#[derive(Clone)]
+pub struct Heap {
+    objects: Vec<Object>,
+    /// A collection is due once this many objects are on the heap.
+    next_gc: usize,
+    /// After a collection the threshold becomes the surviving objects times
+    /// this factor.
+    pub growth_factor: usize,
+    /// Makes a collection due after every allocation, to shake out objects
+    /// that aren't reachable from the roots when they should be.
+    pub stress: bool,
+    due: bool,
+}
+
+impl fmt::Debug for Heap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<heap of {} objects>", self.objects.len())
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            next_gc: MIN_THRESHOLD,
+            growth_factor: 2,
+            stress: false,
+            due: false,
+        }
+    }
+
+    /// Takes ownership of a new object.
+    pub fn track(&mut self, object: Object) {
+        self.objects.push(object);
+        self.due |= self.stress || self.objects.len() >= self.next_gc;
+    }
+
+    /// Whether the next safe point should collect.
+    pub fn is_due(&self) -> bool {
+        self.due
+    }
+
+    /// The number of objects on the heap.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Frees every object that can't be reached from `roots` and returns how
+    /// many there were. Roots may be objects the heap doesn't own, such as
+    /// the global scope.
+    pub fn collect(&mut self, roots: Vec<Object>) -> usize {
+        let mut marked = HashSet::new();
+        let mut pending = roots;
+        while let Some(object) = pending.pop() {
+            if !marked.insert(object.address()) {
+                continue;
+            }
+            object.trace(&mut |child| {
+                if !marked.contains(&child.address()) {
+                    pending.push(child);
+                }
+            });
+        }
+
+        let before = self.objects.len();
+        self.objects.retain(|object| {
+            let reachable = marked.contains(&object.address());
+            if !reachable {
+                object.clear();
+            }
+            reachable
+        });
+        self.next_gc = (self.objects.len() * self.growth_factor).max(MIN_THRESHOLD);
+        self.due = false;
+        before - self.objects.len()
+    }
+}
+
+impl Object {
+    /// The object `value` refers to, if it is one.
+    pub fn of(value: &Data) -> Option<Object> {
+        match value {
+            Data::Str(str) => Some(Object::Str(str.clone())),
+            Data::List(list) => Some(Object::List(list.clone())),
+            Data::Map(map) => Some(Object::Map(map.clone())),
+            Data::Function(function) => Some(Object::Function(function.clone())),
+            Data::Closure(closure) => Some(Object::Closure(closure.clone())),
+            Data::Module(module) => Some(Object::Module(module.clone())),
+            Data::Error(error) => Some(Object::Error(error.clone())),
+            Data::Number(_) | Data::Bool(_) | Data::Nil | Data::Range(_) | Data::Native(_) => None,
+        }
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Object::Str(str) => Str::as_ptr(str) as usize,
+            Object::Env(env) => Rc::as_ptr(env) as *const () as usize,
+            Object::List(list) => Rc::as_ptr(list) as *const () as usize,
+            Object::Map(map) => Rc::as_ptr(map) as *const () as usize,
+            Object::Cell(cell) => Rc::as_ptr(cell) as *const () as usize,
+            Object::Function(function) => Rc::as_ptr(function) as *const () as usize,
+            Object::Closure(closure) => Rc::as_ptr(closure) as *const () as usize,
+            Object::Module(module) => Rc::as_ptr(module) as *const () as usize,
+            Object::Error(error) => Rc::as_ptr(error) as *const () as usize,
+        }
+    }
+
+    /// Calls `visit` with every object this one references.
+    fn trace(&self, visit: &mut dyn FnMut(Object)) {
+        let mut value = |value: &Data| Object::of(value).into_iter().for_each(&mut *visit);
+        match self {
+            Object::Str(_) => (),
+            Object::Env(env) => {
+                let env = env.borrow();
+                env.values.values().for_each(|v| value(&v.get()));
+                if let Some(enclosing) = &env.enclosing {
+                    visit(Object::Env(enclosing.clone()));
+                }
+            }
+            Object::List(list) => list.borrow().iter().for_each(|v| value(&v.get())),
+            Object::Map(map) => {
+                for (key, v) in map.borrow().iter() {
+                    value(&key.to_data());
+                    value(v);
+                }
+            }
+            Object::Cell(cell) => value(&cell.borrow().get()),
+            Object::Function(function) => {
+                for upvalue in function.upvalues.iter() {
+                    visit(Object::Cell(upvalue.clone()));
+                }
+                visit(Object::Env(function.closure.clone()));
+            }
+            Object::Closure(closure) => {
+                for upvalue in &closure.upvalues {
+                    visit(Object::Cell(upvalue.clone()));
+                }
+                visit(Object::Env(closure.globals.clone()));
+            }
+            Object::Module(module) => visit(Object::Env(module.env.clone())),
+            Object::Error(error) => visit(Object::Str(error.message.clone())),
+        }
+    }
+
+    /// Drops the references an unreachable object holds. Functions, closures
+    /// and modules can't be changed, but a cycle always runs through one of
+    /// the mutable objects.
+    fn clear(&self) {
+        match self {
+            Object::Env(env) => {
+                let mut env = env.borrow_mut();
+                env.values.clear();
+                env.enclosing = None;
+            }
+            Object::List(list) => list.borrow_mut().clear(),
+            Object::Map(map) => map.borrow_mut().clear(),
+            Object::Cell(cell) => *cell.borrow_mut() = Value::NIL,
+            Object::Str(_)
+            | Object::Function(_)
+            | Object::Closure(_)
+            | Object::Module(_)
+            | Object::Error(_) => (),
+        }
+    }
+}
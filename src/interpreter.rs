@@ -1,26 +1,89 @@
 use crate::{
     environment::Environment,
-    expr::{Assign, Binary, Call, Expr, Grouping, Logical, Unary, Variable},
+    expr::{
+        Assign, Binary, Call, Conditional, Expr, Get, Grouping, Index, List, Logical, Map,
+        SetIndex, Unary, Variable,
+    },
+    function::LoxFunction,
+    gc::{Heap, Object},
+    iterator::{LoxIterator, Range},
+    map::{Key, LoxMap},
+    module::Module,
+    native::{self, NativeFunction},
+    native_io::Capabilities,
+    native_math::Random,
+    optimizer,
+    parser::Parser,
+    resolver::{self, Layout, Slot},
     scanner::Literal,
-    scanner::Token,
+    scanner::Scanner,
+    scanner::Span,
     scanner::TokenType,
-    stmt::{Block, Expression, If, Print, Stmt, Var, While},
+    stmt::{
+        Block, Expression, ForIn, Function, If, Import, Print, Return, Stmt, Throw, Try, Var, While,
+    },
+    symbol::{Symbol, SymbolMap},
+    value::{Str, Value},
+    vm,
 };
+use std::fmt;
+use std::fs::File;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
-use std::{
-    fmt,
-    time::{SystemTime, UNIX_EPOCH},
-};
 
-#[derive(Clone, Debug, PartialEq)]
+/// Calls nested deeper than this are reported as a stack overflow. Every
+/// call made through `Interpreter::call` recurses on the native stack, so
+/// the limit is lower than the VM's.
+const CALLS_MAX: usize = 1024;
+
+#[derive(Clone, Debug)]
 pub enum Data {
     Number(f64),
-    Str(String),
+    Str(Str),
     Bool(bool),
-    Func,
+    Function(Rc<LoxFunction>),
+    /// A function compiled for the bytecode VM.
+    Closure(Rc<vm::Closure>),
+    Native(Rc<NativeFunction>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    Range(Range),
+    Module(Rc<Module>),
+    Error(Rc<ErrorObject>),
     Nil,
 }
 
+/// What a `catch` clause receives when the interpreter itself failed, as
+/// opposed to a value thrown by the script.
+#[derive(Debug)]
+pub struct ErrorObject {
+    pub message: Str,
+    pub line: Option<usize>,
+}
+
+/// Values compare like Lox values: primitives by value, heap objects by
+/// identity.
+impl PartialEq for Data {
+    fn eq(&self, other: &Data) -> bool {
+        match (self, other) {
+            (Data::Number(a), Data::Number(b)) => a == b,
+            (Data::Str(a), Data::Str(b)) => Str::ptr_eq(a, b) || a == b,
+            (Data::Bool(a), Data::Bool(b)) => a == b,
+            (Data::Function(a), Data::Function(b)) => Rc::ptr_eq(a, b),
+            (Data::Closure(a), Data::Closure(b)) => Rc::ptr_eq(a, b),
+            (Data::Native(a), Data::Native(b)) => Rc::ptr_eq(a, b),
+            (Data::List(a), Data::List(b)) => Rc::ptr_eq(a, b),
+            (Data::Map(a), Data::Map(b)) => Rc::ptr_eq(a, b),
+            (Data::Range(a), Data::Range(b)) => a == b,
+            (Data::Module(a), Data::Module(b)) => Rc::ptr_eq(a, b),
+            (Data::Error(a), Data::Error(b)) => Rc::ptr_eq(a, b),
+            (Data::Nil, Data::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Display for Data {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -28,34 +91,129 @@ impl fmt::Display for Data {
             Data::Str(str) => write!(f, "{}", str),
             Data::Bool(bool) => write!(f, "{}", bool),
             Data::Nil => write!(f, "nil"),
-            Data::Func => write!(f, "func"),
+            Data::Function(function) => write!(f, "{:?}", function),
+            Data::Closure(closure) => write!(f, "{:?}", closure),
+            Data::Native(native) => write!(f, "{:?}", native),
+            Data::List(list) => {
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Data::Range(range) => write!(f, "{}", range),
+            Data::Module(module) => write!(f, "{:?}", module),
+            Data::Error(error) => write!(f, "{}", error.message),
+            Data::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key.to_data(), value)?;
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
 
-impl LoxCallable for Data {}
+/// Executes a freshly parsed module in the given environment.
+pub(crate) type ModuleRunner =
+    fn(&mut Interpreter, &[Stmt], Rc<RefCell<Environment>>) -> Result<(), Error>;
 
-trait LoxCallable {
-    fn arity() {
-        todo!();
-    }
-    fn call(&self, interpreter: &Interpreter, arguments: Vec<Data>) {
-        todo!();
-    }
+pub trait LoxCallable {
+    fn arity(&self) -> RangeInclusive<usize>;
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Span,
+        arguments: Vec<Data>,
+    ) -> Result<Data, Error>;
 }
 
 #[derive(Debug, Clone)]
 pub struct Interpreter {
     pub env: Rc<RefCell<Environment>>,
+    /// The builtins. Every module runs in a scope nested directly inside it.
+    pub globals: Rc<RefCell<Environment>>,
     pub repl: bool,
+    /// Backs `random()` and `random_int()`. Seeded from the clock unless the
+    /// embedder or the `--seed` flag fixes it.
+    pub rng: Random,
+    /// Gates the I/O builtins. Embedders start with no capabilities.
+    pub capabilities: Capabilities,
+    /// Script arguments returned by `args()`.
+    pub args: Vec<String>,
+    /// Set once the script calls `exit()`.
+    pub exit_code: Option<i32>,
+    /// Canonical paths of the files currently executing, innermost last.
+    /// Imports resolve relative to the last one, or to the working directory
+    /// when it is empty.
+    pub importing: Vec<PathBuf>,
+    /// Runs the optimizer over scripts and modules before they execute.
+    pub optimize: bool,
+    /// Modules that finished running, so each file runs at most once.
+    modules: HashMap<PathBuf, Rc<Module>>,
+    /// Owns the objects the program creates. Everything below is a root.
+    pub heap: Heap,
+    /// The local variables of the calls in progress, each call's above its
+    /// caller's, and the values Rust code holds on to while Lox code runs.
+    stack: Vec<Value>,
+    frame: Frame,
+    /// The frames of the callers of the running call.
+    frames: Vec<Frame>,
+    /// The environments `execute_in` will go back to.
+    envs: Vec<Rc<RefCell<Environment>>>,
+    /// The `for` loops in progress on either backend, innermost last.
+    pub(crate) iterators: Vec<LoxIterator>,
+    /// VM runs waiting for a call into other code to return.
+    pub(crate) suspended: Vec<vm::Suspended>,
+    /// The number of calls in progress through `call`.
+    depth: usize,
+}
+
+/// The variables of the running call. Its locals are `stack[base..]`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Frame {
+    base: usize,
+    cells: Vec<Rc<RefCell<Value>>>,
+    upvalues: Rc<[Rc<RefCell<Value>>]>,
 }
 
 #[derive(Debug)]
 pub enum Error {
-    OperandNumberError(Token, String, String),
-    OperandNumbersError(Token, String, String),
-    AdditionError(Token, String, String),
+    OperandNumberError(Span, String, String),
+    OperandNumbersError(Span, String, String),
+    AdditionError(Span, String, String),
+    CallError(Span, String),
+    ArityError(Span, RangeInclusive<usize>, usize),
+    NotIndexable(Span, String),
+    IndexTypeError(Span, String),
+    IndexOutOfRange(Span, f64, usize),
+    KeyTypeError(Span, String),
+    KeyError(Span, String),
+    NotIterable(Span, String),
+    NativeError(Span, String),
+    ImportError(Span, String),
+    PropertyError(Span, String),
+    UndefinedProperty(Span, String),
+    UndefinedVariable(Span),
+    StackOverflow(Span),
+    /// A value raised by `throw`.
+    Thrown(Span, Data),
     ValueError,
+    /// Unwinds to the innermost loop. The parser rejects `break` and
+    /// `continue` outside of loops, so these never reach `interpret`.
+    Break,
+    Continue,
+    /// Unwinds to the function call with the returned value.
+    Return(Data),
+    /// Raised by `exit()` to unwind the whole script.
+    Exit(i32),
 }
 
 impl std::fmt::Display for Error {
@@ -76,209 +234,769 @@ impl std::fmt::Display for Error {
                 "Operands must be two numbers or two strings. Token: {}, Left: {}, Right: {}",
                 token, left, right
             ),
+            Error::CallError(ref token, ref callee) => write!(
+                f,
+                "Can only call functions. Token: {}, Callee: {}",
+                token, callee
+            ),
+            Error::ArityError(ref token, ref expected, got) => {
+                let (min, max) = (expected.start(), expected.end());
+                if min == max {
+                    write!(
+                        f,
+                        "Expected {} arguments but got {}. Token: {}",
+                        min, got, token
+                    )
+                } else {
+                    write!(
+                        f,
+                        "Expected {} to {} arguments but got {}. Token: {}",
+                        min, max, got, token
+                    )
+                }
+            }
+            Error::NotIndexable(ref token, ref object) => write!(
+                f,
+                "Only lists and maps can be indexed. Token: {}, Object: {}",
+                token, object
+            ),
+            Error::IndexTypeError(ref token, ref index) => write!(
+                f,
+                "Index must be a non-negative integer. Token: {}, Index: {}",
+                token, index
+            ),
+            Error::IndexOutOfRange(ref token, index, len) => write!(
+                f,
+                "Index out of range. Token: {}, Index: {}, Length: {}",
+                token, index, len
+            ),
+            Error::KeyTypeError(ref token, ref key) => write!(
+                f,
+                "Map keys must be strings, numbers, booleans or nil. Token: {}, Key: {}",
+                token, key
+            ),
+            Error::KeyError(ref token, ref key) => {
+                write!(f, "Key not found in map. Token: {}, Key: {}", token, key)
+            }
+            Error::NotIterable(ref token, ref value) => write!(
+                f,
+                "Only lists, maps, strings and ranges can be iterated. Token: {}, Value: {}",
+                token, value
+            ),
+            Error::NativeError(ref token, ref message) => {
+                write!(f, "{} Token: {}", message, token)
+            }
+            Error::ImportError(ref token, ref message) => {
+                write!(f, "{} Token: {}", message, token)
+            }
+            Error::PropertyError(ref token, ref object) => write!(
+                f,
+                "Only modules and errors have properties. Token: {}, Object: {}",
+                token, object
+            ),
+            Error::UndefinedProperty(ref token, ref object) => write!(
+                f,
+                "Undefined property '{}' on {}. Token: {}",
+                token.lexeme, object, token
+            ),
+            Error::UndefinedVariable(ref token) => {
+                write!(f, "Undefined variable '{}'. Token: {}", token.lexeme, token)
+            }
+            Error::StackOverflow(ref token) => write!(f, "Stack overflow. Token: {}", token),
+            Error::Thrown(ref token, ref value) => {
+                write!(f, "Uncaught {}. Token: {}", value, token)
+            }
             Error::ValueError => write!(f, "error"),
+            Error::Break => write!(f, "'break' outside of a loop."),
+            Error::Continue => write!(f, "'continue' outside of a loop."),
+            Error::Return(_) => write!(f, "'return' outside of a function."),
+            Error::Exit(code) => write!(f, "exit({})", code),
         }
     }
 }
 
+impl Error {
+    fn token(&self) -> Option<&Span> {
+        match self {
+            Error::OperandNumberError(token, _, _)
+            | Error::OperandNumbersError(token, _, _)
+            | Error::AdditionError(token, _, _)
+            | Error::CallError(token, _)
+            | Error::ArityError(token, _, _)
+            | Error::NotIndexable(token, _)
+            | Error::IndexTypeError(token, _)
+            | Error::IndexOutOfRange(token, _, _)
+            | Error::KeyTypeError(token, _)
+            | Error::KeyError(token, _)
+            | Error::NotIterable(token, _)
+            | Error::NativeError(token, _)
+            | Error::ImportError(token, _)
+            | Error::PropertyError(token, _)
+            | Error::UndefinedProperty(token, _)
+            | Error::UndefinedVariable(token)
+            | Error::StackOverflow(token)
+            | Error::Thrown(token, _) => Some(token),
+            Error::ValueError
+            | Error::Break
+            | Error::Continue
+            | Error::Return(_)
+            | Error::Exit(_) => None,
+        }
+    }
+
+    /// The line the error was raised on, if it is known.
+    pub fn line(&self) -> Option<usize> {
+        self.token().map(|token| token.line)
+    }
+
+    /// The value a `catch` clause binds. Thrown values are caught as they
+    /// are; runtime errors become error objects on the interpreter's heap.
+    /// Control flow such as `break`, `return` and `exit()` can't be caught.
+    pub fn into_value(self, interpreter: &mut Interpreter) -> Result<Data, Error> {
+        match self {
+            Error::Thrown(_, value) => Ok(value),
+            Error::Break | Error::Continue | Error::Return(_) | Error::Exit(_) => Err(self),
+            _ => {
+                let message = Str::from(self.to_string());
+                interpreter.track(Object::Str(message.clone()));
+                let error = Rc::new(ErrorObject {
+                    message,
+                    line: self.line(),
+                });
+                interpreter.track(Object::Error(error.clone()));
+                Ok(Data::Error(error))
+            }
+        }
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Interpreter {
-    pub fn call(&mut self, arguments: Vec<Stmt>) {
-        let start = SystemTime::now();
-        let _since_the_epoch = start.duration_since(UNIX_EPOCH).unwrap().as_secs_f32();
+    /// Creates an interpreter whose global environment holds the builtins.
+    pub fn new() -> Self {
+        let mut globals = Environment {
+            values: SymbolMap::default(),
+            enclosing: None,
+        };
+        native::define_globals(&mut globals);
+        let globals = Rc::new(RefCell::new(globals));
+        Interpreter {
+            env: globals.clone(),
+            globals,
+            repl: false,
+            rng: Random::from_time(),
+            capabilities: Capabilities::none(),
+            args: Vec::new(),
+            exit_code: None,
+            importing: Vec::new(),
+            optimize: false,
+            modules: HashMap::new(),
+            heap: Heap::new(),
+            stack: Vec::new(),
+            frame: Frame::default(),
+            frames: Vec::new(),
+            envs: Vec::new(),
+            iterators: Vec::new(),
+            suspended: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    /// Hands a new object to the heap.
+    pub fn track(&mut self, object: Object) {
+        self.heap.track(object);
+    }
+
+    /// Frees the objects the program can no longer reach and returns how
+    /// many there were. Only call this where no Rust code holds an object
+    /// the roots don't reach; `execute` does so before each statement once
+    /// a collection is due, and the VM at calls and backward jumps.
+    pub fn collect(&mut self) -> usize {
+        let mut roots = vec![
+            Object::Env(self.globals.clone()),
+            Object::Env(self.env.clone()),
+        ];
+        roots.extend(self.envs.iter().cloned().map(Object::Env));
+        roots.extend(self.modules.values().cloned().map(Object::Module));
+        roots.extend(
+            self.stack
+                .iter()
+                .filter_map(|value| Object::of(&value.get())),
+        );
+        for frame in std::iter::once(&self.frame).chain(&self.frames) {
+            let cells = frame.cells.iter().chain(frame.upvalues.iter());
+            roots.extend(cells.cloned().map(Object::Cell));
+        }
+        for iterator in &self.iterators {
+            iterator.trace(&mut |object| roots.push(object));
+        }
+        for run in &self.suspended {
+            run.trace(&mut |object| roots.push(object));
+        }
+        self.heap.collect(roots)
+    }
+
+    /// Keeps `value` where the collector finds it until `release`, or until
+    /// the running statement or builtin returns. Returns the height to
+    /// release to.
+    pub(crate) fn hold(&mut self, value: Data) -> usize {
+        self.stack.push(value.into());
+        self.stack.len() - 1
+    }
+
+    pub(crate) fn release(&mut self, height: usize) {
+        self.stack.truncate(height);
+    }
+
+    /// Passes freshly parsed statements through the optimizer if it is on.
+    pub fn optimized(&self, statements: Vec<Stmt>) -> Vec<Stmt> {
+        match self.optimize {
+            true => optimizer::optimize(statements),
+            false => statements,
+        }
     }
 
     pub fn interpret(&mut self, statements: Vec<Stmt>) {
-        for stmt in statements {
-            self.execute(&stmt)
-            // match execute(stmt) {
-            //     Ok(value) => println!("{:?}", value.to_string()),
-            //     Err(err) => {
-            //         if !repl {
-            //             panic!("Error: {:?}", err.to_string())
-            //         }
-            //         eprintln!("Error: {:?}", err.to_string())
-            //     }
-            // };
+        let layout = match resolver::resolve(&statements) {
+            Ok(layout) => layout,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                return;
+            }
+        };
+        self.push_frame(&layout, Rc::default());
+        for stmt in &statements {
+            match self.execute(stmt) {
+                Ok(()) => (),
+                Err(Error::Exit(code)) => {
+                    self.exit_code = Some(code);
+                    break;
+                }
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    break;
+                }
+            }
         }
+        self.pop_frame();
     }
 
     pub fn evaluate(&mut self, expr: &Expr) -> Result<Data, Error> {
         match expr {
-            Expr::Binary(expr) => self.evaluate_binary(&**expr),
-            Expr::Grouping(grouping) => self.evaluate_grouping(&**grouping),
-            Expr::Literal(lit) => self.evaluate_literal(lit.clone()),
-            Expr::Unary(unary) => self.evaluate_unary(&**unary),
-            Expr::Variable(expr) => self.evaluate_variable_expr(&**expr),
-            Expr::Assign(expr) => self.evaluate_assign_expr(&**expr),
-            Expr::Logical(expr) => self.evaluate_logical_expr(&**expr),
-            Expr::Call(epxr) => todo!(),
+            Expr::Binary(expr) => self.evaluate_binary(expr),
+            Expr::Grouping(grouping) => self.evaluate_grouping(grouping),
+            Expr::Literal(lit) => self.evaluate_literal(lit),
+            Expr::Unary(unary) => self.evaluate_unary(unary),
+            Expr::Variable(expr) => self.evaluate_variable_expr(expr),
+            Expr::Assign(expr) => self.evaluate_assign_expr(expr),
+            Expr::Logical(expr) => self.evaluate_logical_expr(expr),
+            Expr::Call(expr) => self.evaluate_call_expr(expr),
+            Expr::List(expr) => self.evaluate_list_expr(expr),
+            Expr::Map(expr) => self.evaluate_map_expr(expr),
+            Expr::Index(expr) => self.evaluate_index_expr(expr),
+            Expr::SetIndex(expr) => self.evaluate_set_index_expr(expr),
+            Expr::Conditional(expr) => self.evaluate_conditional_expr(expr),
+            Expr::Get(expr) => self.evaluate_get_expr(expr),
+            Expr::Function(expr) => Ok(self.make_function(expr)),
         }
     }
 
-    fn execute(&mut self, stmt: &Stmt) -> () {
-        match stmt {
-            Stmt::Expression(expr) => self.evaluate_expression_stmt(&expr),
-            Stmt::Print(expr) => self.evaluate_print_stmt(&expr),
-            Stmt::Var(expr) => self.evaluate_var_stmt(&expr),
-            Stmt::Block(stmt) => self.evaluate_block_stmt(&stmt),
-            Stmt::If(stmt) => self.evaluate_if_stmt(&stmt),
-            Stmt::While(stmt) => self.evaluate_while_stmt(&**stmt),
-            Stmt::Function(stmt) => todo!(),
+    /// Statements are the tree-walker's safe points: whatever an enclosing
+    /// expression is in the middle of has been held.
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        if self.heap.is_due() {
+            self.collect();
         }
+        let held = self.stack.len();
+        let result = match stmt {
+            Stmt::Expression(expr) => self.evaluate_expression_stmt(expr),
+            Stmt::Print(expr) => self.evaluate_print_stmt(expr),
+            Stmt::Var(expr) => self.evaluate_var_stmt(expr),
+            Stmt::Block(stmt) => self.evaluate_block_stmt(stmt),
+            Stmt::If(stmt) => self.evaluate_if_stmt(stmt),
+            Stmt::While(stmt) => self.evaluate_while_stmt(stmt),
+            Stmt::ForIn(stmt) => self.evaluate_for_in_stmt(stmt),
+            Stmt::Break(_) => Err(Error::Break),
+            Stmt::Continue(_) => Err(Error::Continue),
+            Stmt::Function(stmt) => self.evaluate_function_stmt(stmt),
+            Stmt::Return(stmt) => self.evaluate_return_stmt(stmt),
+            Stmt::Import(stmt) => self.evaluate_import_stmt(stmt),
+            Stmt::Throw(stmt) => self.evaluate_throw_stmt(stmt),
+            Stmt::Try(stmt) => self.evaluate_try_stmt(stmt),
+        };
+        self.release(held);
+        result
     }
 
-    fn execute_block(&mut self, statements: &Vec<Stmt>) {
-        let curr_env = Rc::new(RefCell::new(Environment {
-            values: HashMap::new(),
-            enclosing: Some(self.env.clone()),
-        }));
+    fn execute_all(&mut self, statements: &[Stmt]) -> Result<(), Error> {
+        statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement))
+    }
 
-        let prev_env = self.env.clone();
-        self.env = curr_env;
+    /// Runs `statements` with `env` as the scope of their globals. The
+    /// current environment is put back even when a statement errors or a
+    /// `return` unwinds through them.
+    pub(crate) fn execute_in(
+        &mut self,
+        statements: &[Stmt],
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<(), Error> {
+        let prev_env = std::mem::replace(&mut self.env, env);
+        self.envs.push(prev_env);
+        let result = self.execute_all(statements);
+        self.env = self.envs.pop().unwrap();
+        result
+    }
+
+    /// Runs a module in `env`, in a frame of its own.
+    fn execute_module(
+        &mut self,
+        statements: &[Stmt],
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<(), Error> {
+        let layout = resolver::resolve(statements).map_err(|err| {
+            let token = Span::synthetic(TokenType::Import, "import", err.line());
+            Error::ImportError(token, err.to_string())
+        })?;
+        self.push_frame(&layout, Rc::default());
+        let result = self.execute_in(statements, env);
+        self.pop_frame();
+        result
+    }
 
-        for statement in statements {
-            self.execute(&statement);
+    /// Starts a call whose frame `layout` describes. `pop_frame` goes back
+    /// to the caller's.
+    pub(crate) fn push_frame(&mut self, layout: &Layout, upvalues: Rc<[Rc<RefCell<Value>>]>) {
+        let base = self.stack.len();
+        self.stack.resize(base + layout.locals, Value::NIL);
+        let cells = match layout.cells {
+            0 => Vec::new(),
+            cells => {
+                // Each declaration replaces its placeholder with a new cell.
+                let placeholder = Rc::new(RefCell::new(Value::NIL));
+                vec![placeholder; cells]
+            }
+        };
+        let frame = Frame {
+            base,
+            cells,
+            upvalues,
+        };
+        let caller = std::mem::replace(&mut self.frame, frame);
+        self.frames.push(caller);
+    }
+
+    pub(crate) fn pop_frame(&mut self) {
+        self.stack.truncate(self.frame.base);
+        self.frame = self.frames.pop().unwrap();
+    }
+
+    /// Binds a new variable. A captured variable gets a new cell every time,
+    /// so closures made in different iterations of a loop don't share it.
+    pub(crate) fn define(&mut self, slot: Slot, name: Symbol, value: Data) {
+        match slot {
+            Slot::Global => self.env.borrow_mut().define(name, value),
+            Slot::Local(local) => self.stack[self.frame.base + local] = value.into(),
+            Slot::Cell(cell) => {
+                let value = Rc::new(RefCell::new(value.into()));
+                self.track(Object::Cell(value.clone()));
+                self.frame.cells[cell] = value;
+            }
+            Slot::Upvalue(_) => unreachable!("Upvalues are never declared."),
         }
-        self.env = prev_env;
     }
 
-    fn evaluate_block_stmt(&mut self, stmt: &Block) {
-        self.execute_block(&stmt.statements)
+    fn assign(&mut self, slot: Slot, name: &Span, value: Data) -> Result<(), Error> {
+        match slot {
+            Slot::Global => self
+                .env
+                .borrow_mut()
+                .assign(name, &value)
+                .map_err(|_| Error::UndefinedVariable(*name))?,
+            Slot::Local(local) => self.stack[self.frame.base + local] = value.into(),
+            Slot::Cell(cell) => *self.frame.cells[cell].borrow_mut() = value.into(),
+            Slot::Upvalue(upvalue) => *self.frame.upvalues[upvalue].borrow_mut() = value.into(),
+        }
+        Ok(())
     }
 
-    fn evaluate_expression_stmt(&mut self, stmt: &Expression) {
-        self.evaluate(&stmt.expression).unwrap();
+    fn evaluate_block_stmt(&mut self, stmt: &Block) -> Result<(), Error> {
+        self.execute_all(&stmt.statements)
     }
 
-    fn evaluate_if_stmt(&mut self, stmt: &If) {
-        if is_truthy(self.evaluate(&stmt.condition).unwrap()) {
-            self.execute(&stmt.then_branch);
-        } else if stmt.else_branch.is_some() {
-            self.execute(&stmt.else_branch.clone().unwrap());
+    fn evaluate_function_stmt(&mut self, stmt: &Rc<Function>) -> Result<(), Error> {
+        let name = stmt.name.as_ref().unwrap();
+        let slot = stmt.slot.get();
+        if let Slot::Cell(_) = slot {
+            // The cell exists before the closure so that the function can
+            // refer to itself.
+            self.define(slot, name.lexeme, Data::Nil);
+            let function = self.make_function(stmt);
+            self.assign(slot, name, function)
+        } else {
+            let function = self.make_function(stmt);
+            self.define(slot, name.lexeme, function);
+            Ok(())
         }
     }
 
-    fn evaluate_print_stmt(&mut self, stmt: &Print) {
-        let val = self.evaluate(&stmt.expression);
-        println!("{:?}", val);
+    /// A function value closing over the current scope: the environment for
+    /// its globals and the cells of the variables it captures.
+    fn make_function(&mut self, declaration: &Rc<Function>) -> Data {
+        let upvalues = declaration
+            .layout
+            .borrow()
+            .captures
+            .iter()
+            .map(|capture| match *capture {
+                Slot::Cell(cell) => self.frame.cells[cell].clone(),
+                Slot::Upvalue(upvalue) => self.frame.upvalues[upvalue].clone(),
+                _ => unreachable!("Only cells and upvalues are captured."),
+            })
+            .collect();
+        let function = Rc::new(LoxFunction {
+            declaration: declaration.clone(),
+            closure: self.env.clone(),
+            upvalues,
+        });
+        self.track(Object::Function(function.clone()));
+        Data::Function(function)
     }
 
-    fn evaluate_var_stmt(&mut self, stmt: &Var) {
+    fn evaluate_return_stmt(&mut self, stmt: &Return) -> Result<(), Error> {
+        let value = match &stmt.value {
+            Some(value) => self.evaluate(value)?,
+            None => Data::Nil,
+        };
+        Err(Error::Return(value))
+    }
+
+    fn evaluate_expression_stmt(&mut self, stmt: &Expression) -> Result<(), Error> {
+        self.evaluate(&stmt.expression)?;
+        Ok(())
+    }
+
+    fn evaluate_if_stmt(&mut self, stmt: &If) -> Result<(), Error> {
+        if is_truthy(self.evaluate(&stmt.condition)?) {
+            self.execute(&stmt.then_branch)
+        } else if let Some(else_branch) = &stmt.else_branch {
+            self.execute(else_branch)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn evaluate_print_stmt(&mut self, stmt: &Print) -> Result<(), Error> {
+        let val = self.evaluate(&stmt.expression)?;
+        println!("{}", val);
+        Ok(())
+    }
+
+    fn evaluate_var_stmt(&mut self, stmt: &Var) -> Result<(), Error> {
         let mut value: Data = Data::Nil;
-        if stmt.initializer.is_some() {
-            value = self.evaluate(&stmt.initializer.clone().unwrap()).unwrap();
+        if let Some(initializer) = &stmt.initializer {
+            value = self.evaluate(initializer)?;
+        }
+
+        self.define(stmt.slot.get(), stmt.name.lexeme, value);
+        Ok(())
+    }
+
+    fn evaluate_while_stmt(&mut self, stmt: &While) -> Result<(), Error> {
+        while is_truthy(self.evaluate(&stmt.condition)?) {
+            match self.execute(&stmt.body) {
+                Ok(()) | Err(Error::Continue) => (),
+                Err(Error::Break) => break,
+                Err(e) => return Err(e),
+            }
+            if let Some(increment) = &stmt.increment {
+                self.evaluate(increment)?;
+            }
         }
+        Ok(())
+    }
+
+    /// Every iteration binds the loop variable anew.
+    fn evaluate_for_in_stmt(&mut self, stmt: &ForIn) -> Result<(), Error> {
+        let iterable = self.evaluate(&stmt.iterable)?;
+        let iterator = LoxIterator::new(self, &stmt.keyword, iterable)?;
+        self.iterators.push(iterator);
+        let result = loop {
+            let value = match LoxIterator::next(self, &stmt.keyword) {
+                Ok(Some(value)) => value,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            self.define(stmt.slot.get(), stmt.name.lexeme, value);
+            match self.execute(&stmt.body) {
+                Ok(()) | Err(Error::Continue) => (),
+                Err(Error::Break) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.iterators.pop();
+        result
+    }
+
+    fn evaluate_throw_stmt(&mut self, stmt: &Throw) -> Result<(), Error> {
+        let value = self.evaluate(&stmt.value)?;
+        Err(Error::Thrown(stmt.keyword, value))
+    }
+
+    /// `finally` runs however the rest of the statement ends, except when
+    /// `exit()` stops the script. If it fails itself, its error replaces the
+    /// pending one.
+    fn evaluate_try_stmt(&mut self, stmt: &Try) -> Result<(), Error> {
+        let mut result = self.execute_all(&stmt.body);
+
+        if let Some(catch) = &stmt.catch {
+            result = result.or_else(|err| {
+                let value = err.into_value(self)?;
+                self.define(catch.slot.get(), catch.name.lexeme, value);
+                self.execute_all(&catch.body)
+            });
+        }
+        if let Err(Error::Exit(_)) = result {
+            return result;
+        }
+        if let Some(finally) = &stmt.finally {
+            // A value on its way out waits where the collector sees it.
+            if let Err(Error::Return(value) | Error::Thrown(_, value)) = &result {
+                self.hold(value.clone());
+            }
+            self.execute_all(finally)?;
+        }
+        result
+    }
 
-        self.env
-            .borrow_mut()
-            .define(stmt.name.lexeme.clone(), value);
+    /// Binds the module to the name after `as`, or to the file name without
+    /// its extension.
+    fn evaluate_import_stmt(&mut self, stmt: &Import) -> Result<(), Error> {
+        let module = self.import(&stmt.path, Interpreter::execute_module)?;
+        let name = match &stmt.name {
+            Some(name) => name.lexeme,
+            None => Symbol::intern(&module.name),
+        };
+        self.define(stmt.slot.get(), name, Data::Module(module));
+        Ok(())
     }
 
-    fn evaluate_while_stmt(&mut self, stmt: &While) {
-        while is_truthy(self.evaluate(&stmt.condition).unwrap()) {
-            self.execute(&stmt.body);
+    /// Resolves the module path relative to the importing file and runs the module
+    /// with `run` unless it has been loaded before. `run` executes the parsed
+    /// module in its own environment, so both backends share the lookup,
+    /// caching and cycle detection.
+    pub(crate) fn import(
+        &mut self,
+        path_token: &Span,
+        run: ModuleRunner,
+    ) -> Result<Rc<Module>, Error> {
+        let import_error = |message: String| Error::ImportError(*path_token, message);
+        if !self.capabilities.filesystem {
+            return Err(import_error(String::from(
+                "import requires filesystem access.",
+            )));
         }
-        return;
+        let relative = match path_token.literal() {
+            Literal::Str(path) => path,
+            _ => return Err(Error::ValueError),
+        };
+        let base = match self.importing.last().and_then(|file| file.parent()) {
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::from("."),
+        };
+        let path = base
+            .join(&*relative)
+            .canonicalize()
+            .map_err(|err| import_error(format!("Can't open module '{}': {}.", relative, err)))?;
+
+        if let Some(module) = self.modules.get(&path) {
+            return Ok(module.clone());
+        }
+        if let Some(start) = self.importing.iter().position(|file| *file == path) {
+            let cycle: Vec<String> = self.importing[start..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|file| file_name(file))
+                .collect();
+            return Err(import_error(format!(
+                "Import cycle: {}.",
+                cycle.join(" -> ")
+            )));
+        }
+
+        let source = File::open(&path)
+            .map_err(|err| import_error(format!("Can't open module '{}': {}.", relative, err)))?;
+        let statements = Parser::new(Scanner::new(source))
+            .parse()
+            .map_err(|_| import_error(format!("Can't parse module '{}'.", relative)))?;
+        let statements = self.optimized(statements);
+        let env = Rc::new(RefCell::new(Environment {
+            values: SymbolMap::default(),
+            enclosing: Some(self.globals.clone()),
+        }));
+        self.track(Object::Env(env.clone()));
+
+        self.importing.push(path.clone());
+        let result = run(self, &statements, env.clone());
+        self.importing.pop();
+        result?;
+
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let module = Rc::new(Module {
+            name,
+            path: path.clone(),
+            env,
+        });
+        self.track(Object::Module(module.clone()));
+        self.modules.insert(path, module.clone());
+        Ok(module)
     }
 
     fn evaluate_assign_expr(&mut self, expr: &Assign) -> Result<Data, Error> {
         let val = self.evaluate(&expr.value)?;
-        self.env.borrow_mut().assign(&expr.name, &val);
+        self.assign(expr.slot.get(), &expr.name, val.clone())?;
         Ok(val)
     }
 
     fn evaluate_binary(&mut self, expr: &Binary) -> Result<Data, Error> {
         let left = self.evaluate(&expr.left)?;
+        let held = self.hold(left.clone());
         let right = self.evaluate(&expr.right)?;
+        self.release(held);
+        self.operate(&expr.operator, left, right)
+    }
 
-        match expr.operator.ttype {
-            TokenType::Plus => match (&left, &right) {
-                (Data::Number(left), Data::Number(right)) => Ok(Data::Number(left + right)),
-                (Data::Str(left), Data::Str(right)) => Ok(Data::Str(left.to_owned() + &right)),
-                _ => Err(Error::AdditionError(
-                    expr.operator.clone(),
-                    left.to_string(),
-                    right.to_string(),
-                )),
-            },
-            TokenType::Minus => match (&left, &right) {
-                (Data::Number(left), Data::Number(right)) => Ok(Data::Number(left - right)),
-                _ => Err(Error::OperandNumbersError(
-                    expr.operator.clone(),
-                    left.to_string(),
-                    right.to_string(),
-                )),
-            },
-            TokenType::Slash => match (&left, &right) {
-                (Data::Number(left), Data::Number(right)) => Ok(Data::Number(left / right)),
-                _ => Err(Error::OperandNumbersError(
-                    expr.operator.clone(),
-                    left.to_string(),
-                    right.to_string(),
-                )),
-            },
-            TokenType::Star => match (&left, &right) {
-                (Data::Number(left), Data::Number(right)) => Ok(Data::Number(left * right)),
-                _ => Err(Error::OperandNumbersError(
-                    expr.operator.clone(),
-                    left.to_string(),
-                    right.to_string(),
-                )),
-            },
-            TokenType::Greater => match (&left, &right) {
-                (Data::Number(left), Data::Number(right)) => Ok(Data::Bool(left > right)),
-                _ => Err(Error::OperandNumbersError(
-                    expr.operator.clone(),
-                    left.to_string(),
-                    right.to_string(),
-                )),
-            },
-            TokenType::GreaterEqual => match (&left, &right) {
-                (Data::Number(left), Data::Number(right)) => Ok(Data::Bool(left >= right)),
-                _ => Err(Error::OperandNumbersError(
-                    expr.operator.clone(),
-                    left.to_string(),
-                    right.to_string(),
-                )),
-            },
-            TokenType::Less => match (&left, &right) {
-                (Data::Number(left), Data::Number(right)) => Ok(Data::Bool(left < right)),
-                _ => Err(Error::OperandNumbersError(
-                    expr.operator.clone(),
-                    left.to_string(),
-                    right.to_string(),
-                )),
-            },
-            TokenType::LessEqual => match (&left, &right) {
-                (Data::Number(left), Data::Number(right)) => Ok(Data::Bool(left <= right)),
-                _ => Err(Error::OperandNumbersError(
-                    expr.operator.clone(),
-                    left.to_string(),
-                    right.to_string(),
-                )),
-            },
-            TokenType::BangEqual => Ok(Data::Bool(!is_equal(left, right))),
-            TokenType::EqualEqual => Ok(Data::Bool(is_equal(left, right))),
-            _ => Err(Error::ValueError),
+    /// `binary_op` for a running program: a string made by `+` goes on the
+    /// heap.
+    pub(crate) fn operate(
+        &mut self,
+        operator: &Span,
+        left: Data,
+        right: Data,
+    ) -> Result<Data, Error> {
+        let value = binary_op(operator, left, right)?;
+        if let Data::Str(str) = &value {
+            self.track(Object::Str(str.clone()));
         }
+        Ok(value)
     }
 
     fn evaluate_call_expr(&mut self, expr: &Call) -> Result<Data, Error> {
         let callee = self.evaluate(&expr.callee)?;
-        let mut arguments: Vec<Data> = Vec::new();
-
+        let held = self.hold(callee.clone());
         for argument in &expr.arguments {
-            arguments.push(self.evaluate(argument)?);
+            let argument = self.evaluate(argument)?;
+            self.hold(argument);
         }
-        // TODO: Prevent calling with strings.
-        callee.call(self, arguments);
-        todo!();
+        let arguments = self.stack.split_off(held + 1);
+        let arguments = arguments.into_iter().map(Value::into_data).collect();
+        let result = self.call(&expr.paren, &callee, arguments);
+        self.release(held);
+        result
     }
 
-    fn evaluate_literal(&mut self, literal: Literal) -> Result<Data, Error> {
+    /// Calls any callable value. Builtins that take callbacks go through here
+    /// too, so errors raised inside a callback keep the callback's line.
+    pub fn call(
+        &mut self,
+        paren: &Span,
+        callee: &Data,
+        arguments: Vec<Data>,
+    ) -> Result<Data, Error> {
+        let function: &dyn LoxCallable = match callee {
+            Data::Native(native) => native.as_ref(),
+            Data::Function(function) => function.as_ref(),
+            Data::Closure(closure) => closure,
+            _ => return Err(Error::CallError(*paren, callee.to_string())),
+        };
+        if !function.arity().contains(&arguments.len()) {
+            return Err(Error::ArityError(*paren, function.arity(), arguments.len()));
+        }
+        if self.depth >= CALLS_MAX {
+            return Err(Error::StackOverflow(*paren));
+        }
+        // Builtins get their arguments in a `Vec` of their own, so they are
+        // held for them, along with whatever else the builtin holds.
+        let held = self.stack.len();
+        if let Data::Native(_) = callee {
+            self.stack
+                .extend(arguments.iter().cloned().map(Value::from));
+        }
+        self.depth += 1;
+        let result = function.call(self, paren, arguments);
+        self.depth -= 1;
+        self.release(held);
+        result
+    }
+
+    fn evaluate_get_expr(&mut self, expr: &Get) -> Result<Data, Error> {
+        let object = self.evaluate(&expr.object)?;
+        get_property(&expr.name, &object)
+    }
+
+    fn evaluate_list_expr(&mut self, expr: &List) -> Result<Data, Error> {
+        let held = self.stack.len();
+        for element in &expr.elements {
+            let element = self.evaluate(element)?;
+            self.hold(element);
+        }
+        let elements = self.stack.split_off(held);
+        Ok(native::new_list(self, elements))
+    }
+
+    fn evaluate_map_expr(&mut self, expr: &Map) -> Result<Data, Error> {
+        let held = self.stack.len();
+        for (key, value) in &expr.entries {
+            let key = self.evaluate(key)?;
+            map_key(&expr.brace, &key)?;
+            self.hold(key);
+            let value = self.evaluate(value)?;
+            self.hold(value);
+        }
+        let mut map = LoxMap::new();
+        let mut entries = self.stack.split_off(held).into_iter().map(Value::into_data);
+        while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+            map.insert(map_key(&expr.brace, &key)?, value);
+        }
+        Ok(native::new_map(self, map))
+    }
+
+    fn evaluate_index_expr(&mut self, expr: &Index) -> Result<Data, Error> {
+        let object = self.evaluate(&expr.object)?;
+        let held = self.hold(object.clone());
+        let index = self.evaluate(&expr.index)?;
+        self.release(held);
+        get_index(&expr.bracket, &object, &index)
+    }
+
+    /// The object and index are evaluated once, even for compound
+    /// assignments such as `xs[i] += 1`.
+    fn evaluate_set_index_expr(&mut self, expr: &SetIndex) -> Result<Data, Error> {
+        let object = self.evaluate(&expr.object)?;
+        let held = self.hold(object.clone());
+        let index = self.evaluate(&expr.index)?;
+        self.hold(index.clone());
+        let value = self.evaluate(&expr.value)?;
+        self.release(held);
+        let operator = expr.operator.as_ref();
+        set_index(self, &expr.bracket, &object, &index, operator, value)
+    }
+
+    fn evaluate_literal(&mut self, literal: &Literal) -> Result<Data, Error> {
         match literal {
-            Literal::Str(str) => Ok(Data::Str(str)),
-            Literal::Number(num) => Ok(Data::Number(num)),
+            Literal::Str(str) => Ok(Data::Str(str.clone())),
+            Literal::Number(num) => Ok(Data::Number(*num)),
+            Literal::Bool(bool) => Ok(Data::Bool(*bool)),
+            Literal::Nil => Ok(Data::Nil),
             _ => Err(Error::ValueError),
         }
     }
@@ -289,13 +1007,20 @@ impl Interpreter {
         if expr.operator.ttype == TokenType::Or {
             if is_truthy(left.clone()) {
                 return Ok(left);
-            } else {
-                if !is_truthy(left.clone()) {
-                    return Ok(left);
-                }
             }
+        } else if !is_truthy(left.clone()) {
+            return Ok(left);
+        }
+        self.evaluate(&expr.right)
+    }
+
+    /// Only the branch selected by the condition is evaluated.
+    fn evaluate_conditional_expr(&mut self, expr: &Conditional) -> Result<Data, Error> {
+        if is_truthy(self.evaluate(&expr.condition)?) {
+            self.evaluate(&expr.then_branch)
+        } else {
+            self.evaluate(&expr.else_branch)
         }
-        Ok(self.evaluate(&expr.right))?
     }
 
     fn evaluate_grouping(&mut self, grouping: &Grouping) -> Result<Data, Error> {
@@ -309,24 +1034,204 @@ impl Interpreter {
             TokenType::Minus => match right {
                 Data::Number(right) => Ok(Data::Number(-right)),
                 _ => Err(Error::OperandNumberError(
-                    expr.operator.clone(),
+                    expr.operator,
                     String::new(),
                     right.to_string(),
                 )),
             },
-            TokenType::Bang => Ok(Data::Bool(is_truthy(right))),
-            _ => return Err(Error::ValueError),
+            TokenType::Bang => Ok(Data::Bool(!is_truthy(right))),
+            _ => Err(Error::ValueError),
         }
     }
 
     fn evaluate_variable_expr(&mut self, expr: &Variable) -> Result<Data, Error> {
-        match self.env.borrow_mut().get(&expr.name) {
-            Ok(var) => Ok(var),
-            Err(_) => Err(Error::ValueError),
+        match expr.slot.get() {
+            Slot::Global => self
+                .env
+                .borrow_mut()
+                .get(&expr.name)
+                .map_err(|_| Error::UndefinedVariable(expr.name)),
+            Slot::Local(local) => Ok(self.stack[self.frame.base + local].get()),
+            Slot::Cell(cell) => Ok(self.frame.cells[cell].borrow().get()),
+            Slot::Upvalue(upvalue) => Ok(self.frame.upvalues[upvalue].borrow().get()),
+        }
+    }
+}
+
+pub(crate) fn binary_op(operator: &Span, left: Data, right: Data) -> Result<Data, Error> {
+    match operator.ttype {
+        TokenType::Plus => match (&left, &right) {
+            (Data::Number(left), Data::Number(right)) => Ok(Data::Number(left + right)),
+            (Data::Str(left), Data::Str(right)) => {
+                Ok(Data::Str([&left[..], &right[..]].concat().into()))
+            }
+            _ => Err(Error::AdditionError(
+                *operator,
+                left.to_string(),
+                right.to_string(),
+            )),
+        },
+        TokenType::Minus => match (&left, &right) {
+            (Data::Number(left), Data::Number(right)) => Ok(Data::Number(left - right)),
+            _ => Err(Error::OperandNumbersError(
+                *operator,
+                left.to_string(),
+                right.to_string(),
+            )),
+        },
+        TokenType::Slash => match (&left, &right) {
+            (Data::Number(left), Data::Number(right)) => Ok(Data::Number(left / right)),
+            _ => Err(Error::OperandNumbersError(
+                *operator,
+                left.to_string(),
+                right.to_string(),
+            )),
+        },
+        TokenType::Star => match (&left, &right) {
+            (Data::Number(left), Data::Number(right)) => Ok(Data::Number(left * right)),
+            _ => Err(Error::OperandNumbersError(
+                *operator,
+                left.to_string(),
+                right.to_string(),
+            )),
+        },
+        TokenType::Percent => match (&left, &right) {
+            (Data::Number(left), Data::Number(right)) => Ok(Data::Number(left % right)),
+            _ => Err(Error::OperandNumbersError(
+                *operator,
+                left.to_string(),
+                right.to_string(),
+            )),
+        },
+        TokenType::Greater => match (&left, &right) {
+            (Data::Number(left), Data::Number(right)) => Ok(Data::Bool(left > right)),
+            _ => Err(Error::OperandNumbersError(
+                *operator,
+                left.to_string(),
+                right.to_string(),
+            )),
+        },
+        TokenType::GreaterEqual => match (&left, &right) {
+            (Data::Number(left), Data::Number(right)) => Ok(Data::Bool(left >= right)),
+            _ => Err(Error::OperandNumbersError(
+                *operator,
+                left.to_string(),
+                right.to_string(),
+            )),
+        },
+        TokenType::Less => match (&left, &right) {
+            (Data::Number(left), Data::Number(right)) => Ok(Data::Bool(left < right)),
+            _ => Err(Error::OperandNumbersError(
+                *operator,
+                left.to_string(),
+                right.to_string(),
+            )),
+        },
+        TokenType::LessEqual => match (&left, &right) {
+            (Data::Number(left), Data::Number(right)) => Ok(Data::Bool(left <= right)),
+            _ => Err(Error::OperandNumbersError(
+                *operator,
+                left.to_string(),
+                right.to_string(),
+            )),
+        },
+        TokenType::BangEqual => Ok(Data::Bool(!is_equal(left, right))),
+        TokenType::EqualEqual => Ok(Data::Bool(is_equal(left, right))),
+        _ => Err(Error::ValueError),
+    }
+}
+
+/// Reads a top-level definition of a module or a field of an error object.
+/// Modules are read-only from the outside.
+pub(crate) fn get_property(name: &Span, object: &Data) -> Result<Data, Error> {
+    match object {
+        Data::Module(module) => {
+            let value = module.env.borrow().values.get(&name.lexeme).map(Value::get);
+            value.ok_or_else(|| Error::UndefinedProperty(*name, object.to_string()))
         }
+        Data::Error(error) => match name.lexeme.as_str() {
+            "message" => Ok(Data::Str(error.message.clone())),
+            "line" => Ok(error
+                .line
+                .map_or(Data::Nil, |line| Data::Number(line as f64))),
+            _ => Err(Error::UndefinedProperty(*name, String::from("error"))),
+        },
+        _ => Err(Error::PropertyError(*name, object.to_string())),
     }
 }
-fn is_truthy(data: Data) -> bool {
+
+pub(crate) fn get_index(bracket: &Span, object: &Data, index: &Data) -> Result<Data, Error> {
+    match object {
+        Data::List(list) => {
+            let list = list.borrow();
+            let i = list_index(bracket, index, list.len())?;
+            Ok(list[i].get())
+        }
+        Data::Map(map) => {
+            let key = map_key(bracket, index)?;
+            let value = map.borrow().get(&key).cloned();
+            value.ok_or_else(|| Error::KeyError(*bracket, index.to_string()))
+        }
+        _ => Err(Error::NotIndexable(*bracket, object.to_string())),
+    }
+}
+
+/// Stores `value` at `index`, first combining it with the current element
+/// when `operator` is given. Returns the stored value.
+pub(crate) fn set_index(
+    interpreter: &mut Interpreter,
+    bracket: &Span,
+    object: &Data,
+    index: &Data,
+    operator: Option<&Span>,
+    mut value: Data,
+) -> Result<Data, Error> {
+    match object {
+        Data::List(list) => {
+            let i = list_index(bracket, index, list.borrow().len())?;
+            if let Some(operator) = operator {
+                let current = list.borrow()[i].get();
+                value = interpreter.operate(operator, current, value)?;
+            }
+            list.borrow_mut()[i] = value.clone().into();
+        }
+        Data::Map(map) => {
+            let key = map_key(bracket, index)?;
+            if let Some(operator) = operator {
+                let current = map.borrow().get(&key).cloned();
+                let current =
+                    current.ok_or_else(|| Error::KeyError(*bracket, index.to_string()))?;
+                value = interpreter.operate(operator, current, value)?;
+            }
+            map.borrow_mut().insert(key, value.clone());
+        }
+        _ => return Err(Error::NotIndexable(*bracket, object.to_string())),
+    }
+    Ok(value)
+}
+
+pub fn map_key(token: &Span, key: &Data) -> Result<Key, Error> {
+    Key::from_data(key).ok_or_else(|| Error::KeyTypeError(*token, key.to_string()))
+}
+
+fn list_index(bracket: &Span, index: &Data, len: usize) -> Result<usize, Error> {
+    let i = match index {
+        Data::Number(i) if i.fract() == 0.0 && *i >= 0.0 => *i,
+        _ => return Err(Error::IndexTypeError(*bracket, index.to_string())),
+    };
+    if i >= len as f64 {
+        return Err(Error::IndexOutOfRange(*bracket, i, len));
+    }
+    Ok(i as usize)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+pub fn is_truthy(data: Data) -> bool {
     match data {
         Data::Nil => false,
         Data::Bool(val) => val,
@@ -334,7 +1239,7 @@ fn is_truthy(data: Data) -> bool {
     }
 }
 
-fn is_equal(a: Data, b: Data) -> bool {
+pub(crate) fn is_equal(a: Data, b: Data) -> bool {
     if a == Data::Nil && b == Data::Nil {
         return true;
     }
@@ -0,0 +1,130 @@
+use crate::{
+    gc::Object,
+    interpreter::{Data, Error, Interpreter},
+    map::Key,
+    native::new_string,
+    scanner::Span,
+    symbol::Symbol,
+    value::Value,
+};
+use std::{cell::RefCell, fmt, rc::Rc, vec};
+
+/// The lazy sequence returned by `range(start, end, step)`. Ranges are plain
+/// values, so the same range can be iterated any number of times.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+impl Range {
+    pub fn len(&self) -> usize {
+        ((self.end - self.start) / self.step).ceil().max(0.0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "range({}, {}, {})", self.start, self.end, self.step)
+    }
+}
+
+/// The state of a `for (var x in iterable)` loop. The interpreter keeps the
+/// loops in progress in `Interpreter::iterators`, where the collector finds
+/// what they still have to visit.
+#[derive(Clone, Debug)]
+pub enum LoxIterator {
+    /// Lists are walked by index, so elements pushed during the loop are
+    /// visited too.
+    List(Rc<RefCell<Vec<Value>>>, usize),
+    /// Map keys and string characters are snapshotted when the loop starts.
+    Values(vec::IntoIter<Data>),
+    Range(Range, f64),
+    /// A map with a callable `next` entry, which is called with no arguments
+    /// for each element. The loop ends when it returns `nil`.
+    Object(Data),
+}
+
+impl LoxIterator {
+    pub fn new(
+        interpreter: &mut Interpreter,
+        keyword: &Span,
+        iterable: Data,
+    ) -> Result<LoxIterator, Error> {
+        match iterable {
+            Data::List(list) => Ok(LoxIterator::List(list, 0)),
+            Data::Map(map) => {
+                let key = Key::Str(Symbol::from_static("next").to_str());
+                if let Some(next @ (Data::Function(_) | Data::Closure(_) | Data::Native(_))) =
+                    map.borrow().get(&key)
+                {
+                    return Ok(LoxIterator::Object(next.clone()));
+                }
+                let keys: Vec<Data> = map.borrow().iter().map(|(key, _)| key.to_data()).collect();
+                Ok(LoxIterator::Values(keys.into_iter()))
+            }
+            Data::Str(str) => {
+                let chars: Vec<Data> = str
+                    .chars()
+                    .map(|c| new_string(interpreter, c.to_string()))
+                    .collect();
+                Ok(LoxIterator::Values(chars.into_iter()))
+            }
+            Data::Range(range) => Ok(LoxIterator::Range(range, range.start)),
+            other => Err(Error::NotIterable(*keyword, other.to_string())),
+        }
+    }
+
+    /// The next element of the innermost loop, or `None` once it is over.
+    /// Only iterating an object runs Lox code, so only that can fail.
+    pub fn next(interpreter: &mut Interpreter, keyword: &Span) -> Result<Option<Data>, Error> {
+        let value = match interpreter.iterators.last_mut().unwrap() {
+            LoxIterator::List(list, next) => {
+                let value = list.borrow().get(*next).map(Value::get);
+                *next += 1;
+                value
+            }
+            LoxIterator::Values(values) => values.next(),
+            LoxIterator::Range(range, current) => {
+                let done = if range.step > 0.0 {
+                    *current >= range.end
+                } else {
+                    *current <= range.end
+                };
+                if done {
+                    return Ok(None);
+                }
+                let value = *current;
+                *current += range.step;
+                Some(Data::Number(value))
+            }
+            LoxIterator::Object(next) => {
+                let next = next.clone();
+                match interpreter.call(keyword, &next, Vec::new())? {
+                    Data::Nil => None,
+                    value => Some(value),
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    /// Calls `visit` with every object the loop has yet to visit.
+    pub fn trace(&self, visit: &mut dyn FnMut(Object)) {
+        match self {
+            LoxIterator::List(list, _) => visit(Object::List(list.clone())),
+            LoxIterator::Values(values) => values
+                .as_slice()
+                .iter()
+                .filter_map(Object::of)
+                .for_each(visit),
+            LoxIterator::Range(..) => (),
+            LoxIterator::Object(next) => Object::of(next).into_iter().for_each(visit),
+        }
+    }
+}
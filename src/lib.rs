@@ -1,13 +1,38 @@
 mod ast_printer;
+pub mod chunk;
+pub mod compiler;
+pub mod disassembler;
 mod environment;
 mod expr;
+pub mod function;
+pub mod gc;
 pub mod interpreter;
+pub mod iterator;
+pub mod loxc;
+pub mod map;
+pub mod module;
+pub mod native;
+pub mod native_io;
+pub mod native_math;
+mod native_list;
+mod native_string;
+pub mod optimizer;
 mod parser;
+mod resolver;
 pub mod scanner;
 mod stmt;
+pub mod symbol;
 mod test_ast_printer;
+mod test_interpreter;
+mod test_optimizer;
+mod test_scanner;
+mod test_value;
+mod test_vm;
+pub mod value;
+pub mod vm;
 
 pub use environment::Environment;
 pub use interpreter::Interpreter;
 pub use parser::Parser;
-pub use scanner::{Scanner, Token};
+pub use scanner::{Error as ScanError, Scanner, Span, Token};
+pub use stmt::Stmt;
@@ -0,0 +1,548 @@
+//! The `.loxc` file format for compiled programs.
+//!
+//! All integers are big-endian. A file is a header followed by the script's
+//! prototype:
+//!
+//! ```text
+//! magic     "LOXC"
+//! version   u16
+//! checksum  u32, FNV-1a of everything after the header
+//! prototype name, arity, cells, captures, code, line table, span table,
+//!           constants
+//! ```
+//!
+//! Function constants nest their prototypes in place. Line tables are stored
+//! as runs of `(line, byte count)`. Span tables hold `(instruction end,
+//! lexeme, line, start, end)`; the token type is recovered by scanning the
+//! lexeme.
+//!
+//! Loading checks the checksum and validates every instruction, so a
+//! truncated or damaged file is reported as an error instead of crashing the
+//! VM.
+
+use crate::{
+    chunk::{Capture, Chunk, Constant, OpCode, Prototype},
+    scanner::{Scanner, Span, TokenType},
+    symbol::Symbol,
+};
+use std::{fmt, rc::Rc};
+
+const MAGIC: &[u8; 4] = b"LOXC";
+/// Bump whenever the encoding or the instruction set changes.
+pub const VERSION: u16 = 3;
+const HEADER_LEN: usize = 10;
+/// Deeper nesting than this can't come from the compiler.
+const MAX_NESTING: usize = 256;
+
+#[derive(Debug)]
+pub enum Error {
+    NotCompiled,
+    Version(u16),
+    Checksum,
+    Truncated,
+    Malformed(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotCompiled => write!(f, "Not a compiled Lox file."),
+            Error::Version(version) => write!(
+                f,
+                "Compiled file has format version {}, expected {}. Recompile it.",
+                version, VERSION
+            ),
+            Error::Checksum => write!(f, "Compiled file is corrupted (checksum mismatch)."),
+            Error::Truncated => write!(f, "Compiled file is truncated."),
+            Error::Malformed(message) => write!(f, "Compiled file is malformed: {}", message),
+        }
+    }
+}
+
+/// Encodes a compiled script.
+pub fn write(script: &Prototype) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_prototype(&mut body, script);
+
+    let mut out = Vec::with_capacity(HEADER_LEN + body.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_be_bytes());
+    out.extend_from_slice(&checksum(&body).to_be_bytes());
+    out.extend_from_slice(&body);
+    out
+}
+
+/// Decodes and validates a compiled script.
+pub fn read(bytes: &[u8]) -> Result<Rc<Prototype>, Error> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(Error::NotCompiled);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(Error::Truncated);
+    }
+    let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(Error::Version(version));
+    }
+    let expected = u32::from_be_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    let body = &bytes[HEADER_LEN..];
+    if checksum(body) != expected {
+        return Err(Error::Checksum);
+    }
+
+    let mut reader = Reader {
+        bytes: body,
+        pos: 0,
+    };
+    let script = reader.prototype(None, 0)?;
+    if reader.pos != body.len() {
+        return Err(malformed("trailing bytes after the script"));
+    }
+    Ok(Rc::new(script))
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash: u32, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn malformed(message: &str) -> Error {
+    Error::Malformed(message.to_string())
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_be_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, str: &str) {
+    write_u32(out, str.len());
+    out.extend_from_slice(str.as_bytes());
+}
+
+fn write_prototype(out: &mut Vec<u8>, prototype: &Prototype) {
+    match &prototype.name {
+        Some(name) => {
+            out.push(1);
+            write_str(out, name);
+        }
+        None => out.push(0),
+    }
+    write_u32(out, prototype.arity);
+    write_u32(out, prototype.cells);
+
+    write_u32(out, prototype.captures.len());
+    for capture in &prototype.captures {
+        match *capture {
+            Capture::Cell(cell) => out.extend_from_slice(&[0, cell]),
+            Capture::Upvalue(upvalue) => out.extend_from_slice(&[1, upvalue]),
+        }
+    }
+
+    let chunk = &prototype.chunk;
+    write_u32(out, chunk.code.len());
+    out.extend_from_slice(&chunk.code);
+
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for &line in &chunk.lines {
+        match runs.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => runs.push((line, 1)),
+        }
+    }
+    write_u32(out, runs.len());
+    for (line, count) in runs {
+        write_u32(out, line);
+        write_u32(out, count);
+    }
+
+    write_u32(out, chunk.spans.len());
+    for (offset, span) in &chunk.spans {
+        write_u32(out, *offset);
+        write_str(out, span.lexeme.as_str());
+        write_u32(out, span.line);
+        write_u32(out, span.start as usize);
+        write_u32(out, span.end as usize);
+    }
+
+    write_u32(out, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Number(num) => {
+                out.push(0);
+                out.extend_from_slice(&num.to_be_bytes());
+            }
+            Constant::Str(str) => {
+                out.push(1);
+                write_str(out, str);
+            }
+            Constant::Function(function) => {
+                out.push(2);
+                write_prototype(out, function);
+            }
+            Constant::Name(name) => {
+                out.push(3);
+                write_str(out, name.as_str());
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(len).ok_or(Error::Truncated)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(Error::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn f64(&mut self) -> Result<f64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(f64::from_be_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| malformed("string is not UTF-8"))
+    }
+
+    fn span(&mut self) -> Result<Span, Error> {
+        let lexeme = self.string()?;
+        let line = self.u32()?;
+        let start = self.u32()? as u32;
+        let end = self.u32()? as u32;
+        let mut tokens = Scanner::new(lexeme.as_bytes());
+        match (tokens.next(), tokens.next()) {
+            (Some(Ok(token)), Some(Ok(eof)))
+                if token.lexeme.as_str() == lexeme && eof.ttype == TokenType::Eof =>
+            {
+                Ok(Span {
+                    line,
+                    start,
+                    end,
+                    ..token.span()
+                })
+            }
+            _ => Err(malformed("span is not a single token")),
+        }
+    }
+
+    /// `enclosing` is the prototype the function is nested in, which its
+    /// captures refer to.
+    fn prototype(
+        &mut self,
+        enclosing: Option<&Prototype>,
+        depth: usize,
+    ) -> Result<Prototype, Error> {
+        if depth > MAX_NESTING {
+            return Err(malformed("functions nested too deeply"));
+        }
+        let name = match self.u8()? {
+            0 => None,
+            1 => Some(self.string()?),
+            _ => return Err(malformed("bad function name tag")),
+        };
+        let arity = self.u32()?;
+        let cells = self.u32()?;
+        if cells > u8::MAX as usize + 1 {
+            return Err(malformed("too many cells"));
+        }
+
+        let count = self.u32()?;
+        let mut captures = Vec::new();
+        for _ in 0..count {
+            let tag = self.u8()?;
+            let index = self.u8()?;
+            let capture = match tag {
+                0 => Capture::Cell(index),
+                1 => Capture::Upvalue(index),
+                _ => return Err(malformed("bad capture tag")),
+            };
+            let valid = match (capture, enclosing) {
+                (Capture::Cell(cell), Some(enclosing)) => (cell as usize) < enclosing.cells,
+                (Capture::Upvalue(upvalue), Some(enclosing)) => {
+                    (upvalue as usize) < enclosing.captures.len()
+                }
+                (_, None) => false,
+            };
+            if !valid {
+                return Err(malformed("capture refers to a missing variable"));
+            }
+            captures.push(capture);
+        }
+
+        let len = self.u32()?;
+        let code = self.take(len)?.to_vec();
+
+        let runs = self.u32()?;
+        let mut lines = Vec::with_capacity(code.len());
+        for _ in 0..runs {
+            let line = self.u32()?;
+            let count = self.u32()?;
+            if lines.len() + count > code.len() {
+                return Err(malformed("line table is longer than the code"));
+            }
+            lines.extend(std::iter::repeat_n(line, count));
+        }
+        if lines.len() != code.len() {
+            return Err(malformed("line table is shorter than the code"));
+        }
+
+        let count = self.u32()?;
+        let mut spans: Vec<(usize, Span)> = Vec::new();
+        for _ in 0..count {
+            let offset = self.u32()?;
+            let span = self.span()?;
+            if offset > code.len() || spans.last().is_some_and(|&(last, _)| last >= offset) {
+                return Err(malformed("span table is out of order"));
+            }
+            spans.push((offset, span));
+        }
+
+        let mut prototype = Prototype {
+            name,
+            arity,
+            cells,
+            captures,
+            chunk: Chunk {
+                code,
+                lines,
+                spans,
+                constants: Vec::new(),
+            },
+        };
+        let count = self.u32()?;
+        for _ in 0..count {
+            let constant = match self.u8()? {
+                0 => Constant::Number(self.f64()?),
+                1 => Constant::Str(self.string()?.into()),
+                2 => Constant::Function(Rc::new(self.prototype(Some(&prototype), depth + 1)?)),
+                3 => Constant::Name(Symbol::intern(&self.string()?)),
+                _ => return Err(malformed("bad constant tag")),
+            };
+            prototype.chunk.constants.push(constant);
+        }
+        validate(&prototype)?;
+        Ok(prototype)
+    }
+}
+
+/// Checks that every instruction decodes, that its operands point at
+/// constants of the right kind, existing cells and upvalues, and that jumps
+/// land on instructions. The code must end in `Return`, and the stack must
+/// be deep enough for every instruction, see `check_stack`.
+fn validate(prototype: &Prototype) -> Result<(), Error> {
+    let chunk = &prototype.chunk;
+    let code = &chunk.code;
+    let mut starts = vec![false; code.len() + 1];
+    let mut targets = Vec::new();
+    let mut last = None;
+    let mut offset = 0;
+
+    while offset < code.len() {
+        starts[offset] = true;
+        let op = OpCode::from_byte(code[offset]).ok_or_else(|| malformed("unknown opcode"))?;
+        let len = op.operand_len();
+        if offset + 1 + len > code.len() {
+            return Err(malformed("instruction runs past the end of the code"));
+        }
+        let u16_at = |at: usize| chunk.read_u16(offset + at) as usize;
+        let constant = |index: usize| chunk.constants.get(index);
+        let next = offset + 1 + len;
+        let operand = code.get(offset + 1).copied().unwrap_or(0) as usize;
+        let problem = match op {
+            OpCode::Constant => match constant(u16_at(1)) {
+                Some(Constant::Number(_) | Constant::Str(_)) => None,
+                _ => Some("constant operand is not a value"),
+            },
+            OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::Import => match constant(u16_at(1)) {
+                Some(Constant::Name(_)) => None,
+                _ => Some("name operand is not a name"),
+            },
+            OpCode::Closure => match constant(u16_at(1)) {
+                Some(Constant::Function(_)) => None,
+                _ => Some("closure operand is not a function"),
+            },
+            OpCode::GetCell | OpCode::SetCell | OpCode::DefineCell | OpCode::NewCell
+                if operand >= prototype.cells =>
+            {
+                Some("cell out of range")
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue if operand >= prototype.captures.len() => {
+                Some("upvalue out of range")
+            }
+            OpCode::SetIndexOp => match OpCode::from_byte(operand as u8) {
+                Some(
+                    OpCode::Add
+                    | OpCode::Subtract
+                    | OpCode::Multiply
+                    | OpCode::Divide
+                    | OpCode::Modulo,
+                ) => None,
+                _ => Some("bad compound assignment operator"),
+            },
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::IterNext | OpCode::PushHandler => {
+                targets.push(offset + 3 + u16_at(1));
+                None
+            }
+            OpCode::Loop => match (offset + 3).checked_sub(u16_at(1)) {
+                Some(target) => {
+                    targets.push(target);
+                    None
+                }
+                None => Some("loop jumps before the code"),
+            },
+            _ => None,
+        };
+        let problem = problem.or_else(|| match op {
+            OpCode::GetIndex
+            | OpCode::SetIndex
+            | OpCode::SetIndexOp
+            | OpCode::Map
+            | OpCode::Import
+                if chunk.span(next).is_none() =>
+            {
+                Some("instruction has no source token")
+            }
+            _ => None,
+        });
+        if let Some(problem) = problem {
+            return Err(malformed(problem));
+        }
+        last = Some(op);
+        offset = next;
+    }
+
+    if last != Some(OpCode::Return) {
+        return Err(malformed("code does not end in a return"));
+    }
+    if targets
+        .into_iter()
+        .any(|target| target >= code.len() || !starts[target])
+    {
+        return Err(malformed("jump does not land on an instruction"));
+    }
+    check_stack(prototype)
+}
+
+/// Follows every path through the code, tracking how many values the frame
+/// has on the stack, so that no instruction pops more than is there or reads
+/// a local slot past the top. Paths that meet must agree on the height.
+/// Assumes `validate` has checked operands and jump targets.
+fn check_stack(prototype: &Prototype) -> Result<(), Error> {
+    let chunk = &prototype.chunk;
+    let mut heights: Vec<Option<usize>> = vec![None; chunk.code.len()];
+    // Slot 0 holds the callee, followed by the arguments.
+    let mut pending = vec![(0, prototype.arity + 1)];
+
+    while let Some((offset, height)) = pending.pop() {
+        match heights[offset] {
+            Some(seen) if seen == height => continue,
+            Some(_) => return Err(malformed("stack height differs between paths")),
+            None => heights[offset] = Some(height),
+        }
+        let op = OpCode::from_byte(chunk.code[offset]).unwrap();
+        let operand = chunk.code.get(offset + 1).copied().unwrap_or(0) as usize;
+        let u16_at = |at: usize| chunk.read_u16(offset + at) as usize;
+        let next = offset + 1 + op.operand_len();
+        let jump = || offset + 3 + u16_at(1);
+
+        let (pops, pushes) = match op {
+            OpCode::GetLocal | OpCode::SetLocal if operand >= height => {
+                return Err(malformed("local slot out of range"));
+            }
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetLocal
+            | OpCode::GetGlobal
+            | OpCode::GetCell
+            | OpCode::GetUpvalue
+            | OpCode::Closure
+            | OpCode::Import => (0, 1),
+            OpCode::Pop
+            | OpCode::DefineGlobal
+            | OpCode::DefineCell
+            | OpCode::Print
+            | OpCode::IterStart
+            | OpCode::Throw
+            | OpCode::Return => (1, 0),
+            OpCode::SetLocal
+            | OpCode::SetGlobal
+            | OpCode::SetCell
+            | OpCode::SetUpvalue
+            | OpCode::GetProperty
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::JumpIfFalse => (1, 1),
+            OpCode::GetIndex
+            | OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Modulo => (2, 1),
+            OpCode::SetIndex | OpCode::SetIndexOp => (3, 1),
+            OpCode::Call => (operand + 1, 1),
+            OpCode::List => (u16_at(1), 1),
+            OpCode::Map => (2 * u16_at(1), 1),
+            OpCode::NewCell
+            | OpCode::Jump
+            | OpCode::Loop
+            | OpCode::IterNext
+            | OpCode::IterEnd
+            | OpCode::PushHandler
+            | OpCode::PopHandler => (0, 0),
+        };
+        let height = match height.checked_sub(pops) {
+            Some(height) => height + pushes,
+            None => return Err(malformed("instruction pops more than is on the stack")),
+        };
+
+        match op {
+            OpCode::Return | OpCode::Throw => {}
+            OpCode::Jump => pending.push((jump(), height)),
+            OpCode::Loop => pending.push((offset + 3 - u16_at(1), height)),
+            OpCode::JumpIfFalse => pending.extend([(jump(), height), (next, height)]),
+            // The next value is pushed only when the loop goes on.
+            OpCode::IterNext => pending.extend([(jump(), height), (next, height + 1)]),
+            // The handler starts with the stack cut back to `slots` and the
+            // caught value on top.
+            OpCode::PushHandler => {
+                let slots = u16_at(3);
+                if slots > height {
+                    return Err(malformed("handler keeps more slots than are on the stack"));
+                }
+                pending.extend([(jump(), slots + 1), (next, height)]);
+            }
+            _ => pending.push((next, height)),
+        }
+    }
+    Ok(())
+}
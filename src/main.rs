@@ -1,89 +1,215 @@
-use rlox::{Environment, Interpreter, Parser, Scanner, Token};
-use std::cell::RefCell;
-use std::collections::HashMap;
+use rlox::native_io::Capabilities;
+use rlox::native_math::Random;
+use rlox::{compiler, disassembler, loxc, vm, Interpreter, Parser, Scanner, Stmt};
 use std::env;
-use std::io::Write;
-use std::rc::Rc;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::process;
+use std::thread;
+
+const USAGE: &str =
+    "Usage: rlox [--seed n] [--vm | --dump-bytecode] [--gc-stress] [--optimize] [script [args...]]
+       rlox compile script.lox [-o script.loxc]";
+
+/// The tree-walker recurses on the native stack for every Lox call, so
+/// scripts run on a thread with room for its deepest calls.
+const STACK_SIZE: usize = 64 << 20;
 
 fn main() {
+    let runner = thread::Builder::new().stack_size(STACK_SIZE).spawn(run);
+    if let Err(panic) = runner.expect("Unable to start the interpreter").join() {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+fn run() {
     env::set_var("RUST_BACKTRACE", "1");
     let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("compile") {
+        compile(&args[2..]);
+        return;
+    }
 
-    let env = Rc::new(RefCell::new(Environment {
-        values: HashMap::new(),
-        enclosing: None,
-    }));
-    let mut interpreter = Interpreter { env, repl: false };
-
-    if args.len() > 2 {
-        print!("Usage: rlox [script]");
-    } else if args.len() == 2 {
-        run_file(&args[1], &mut interpreter);
-    } else {
-        run_prompt(&mut interpreter);
+    let mut interpreter = Interpreter::new();
+    interpreter.capabilities = Capabilities::all();
+
+    // Flags come before the script; everything after it belongs to the script.
+    let mut rest = &args[1..];
+    let mut backend: Backend = Interpreter::interpret;
+    while let Some(flag) = rest.first().filter(|arg| arg.starts_with("--")) {
+        match (
+            flag.as_str(),
+            rest.get(1).and_then(|n| n.parse::<u64>().ok()),
+        ) {
+            // Makes random() and random_int() reproducible.
+            ("--seed", Some(seed)) => {
+                interpreter.rng = Random::new(seed);
+                rest = &rest[1..];
+            }
+            // Compiles to bytecode and runs it on the VM instead of walking
+            // the syntax tree.
+            ("--vm", _) => {
+                backend = |interpreter, statements| vm::interpret(interpreter, &statements)
+            }
+            // Collects at the first safe point after every allocation.
+            ("--gc-stress", _) => interpreter.heap.stress = true,
+            // Folds constant expressions and drops dead branches first.
+            ("--optimize", _) => interpreter.optimize = true,
+            // Prints the compiled bytecode without running it.
+            ("--dump-bytecode", _) => {
+                backend = |_, statements| match compiler::compile(&statements) {
+                    Ok(script) => print!("{}", disassembler::disassemble(&script)),
+                    Err(err) => eprintln!("Error: {}", err),
+                }
+            }
+            _ => {
+                print!("{}", USAGE);
+                return;
+            }
+        }
+        rest = &rest[1..];
+    }
+
+    match rest.split_first() {
+        Some((script, script_args)) => {
+            interpreter.args = script_args.to_vec();
+            run_file(script, &mut interpreter, backend);
+        }
+        None => run_prompt(&mut interpreter, backend),
+    }
+    if let Some(code) = interpreter.exit_code {
+        process::exit(code);
     }
-    dbg!(args);
 }
 
-fn run_file(path: &String, interpreter: &mut Interpreter) {
-    let source = std::fs::read(&path).expect("Unable to open file");
-    let v: Vec<Token> = vec![];
-    let mut scanner = Scanner {
-        source,
-        list: v,
-        current: 0,
-        start: 0,
-        line: 0,
+/// Runs parsed statements: the tree-walking interpreter or the bytecode VM.
+type Backend = fn(&mut Interpreter, Vec<Stmt>);
+
+/// `rlox compile in.lox [-o out.loxc]`. The output defaults to the input
+/// path with a `.loxc` extension.
+fn compile(args: &[String]) {
+    let (input, output) = match args {
+        [input] => (input, Path::new(input).with_extension("loxc")),
+        [input, flag, output] if flag == "-o" => (input, Path::new(output).to_path_buf()),
+        _ => {
+            println!("{}", USAGE);
+            process::exit(64);
+        }
+    };
+    let source = File::open(input).expect("Unable to open file");
+    let statements = match Parser::new(Scanner::new(source)).parse() {
+        Ok(statements) => statements,
+        Err(e) => {
+            println!("{:?}", e);
+            process::exit(65);
+        }
     };
-    scanner.scan_tokens();
-    let mut parser = Parser {
-        tokens: scanner.list,
-        current: 0,
+    let script = match compiler::compile(&statements) {
+        Ok(script) => script,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(65);
+        }
     };
+    if let Err(err) = fs::write(&output, loxc::write(&script)) {
+        eprintln!("Error: Unable to write {}: {}", output.display(), err);
+        process::exit(74);
+    }
+}
+
+fn run_file(path: &str, interpreter: &mut Interpreter, backend: Backend) {
+    if Path::new(path).extension().is_some_and(|ext| ext == "loxc") {
+        return run_compiled(path, interpreter);
+    }
+    let source = File::open(path).expect("Unable to open file");
+    // Imports in the script resolve relative to its directory.
+    if let Ok(path) = Path::new(path).canonicalize() {
+        interpreter.importing.push(path);
+    }
+    let mut parser = Parser::new(Scanner::new(source));
 
     match parser.parse() {
         Ok(stmt) => {
-            interpreter.interpret(stmt);
+            let stmt = interpreter.optimized(stmt);
+            backend(interpreter, stmt);
         }
         Err(e) => println!("{:?}", e),
     };
 }
 
-fn run_prompt(interpreter: &mut Interpreter) {
-    loop {
-        let mut line = String::new();
-        print!(">");
-        std::io::stdout().flush().unwrap();
-        std::io::stdin()
-            .read_line(&mut line)
-            .expect("Error: Could not read a line");
-        line = line.trim().to_string();
-        if line.is_empty() {
+/// Compiled files skip the scanner and parser and always run on the VM.
+fn run_compiled(path: &str, interpreter: &mut Interpreter) {
+    let bytes = fs::read(path).expect("Unable to open file");
+    match loxc::read(&bytes) {
+        Ok(script) => {
+            if let Ok(path) = Path::new(path).canonicalize() {
+                interpreter.importing.push(path);
+            }
+            vm::interpret_compiled(interpreter, script);
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            interpreter.exit_code = Some(65);
+        }
+    }
+}
+
+fn run_prompt(interpreter: &mut Interpreter, backend: Backend) {
+    interpreter.repl = true;
+    let parser = Parser::new(Scanner::new(Prompt::new()));
+
+    for stmt in parser {
+        match stmt {
+            Ok(stmt) => {
+                let stmt = interpreter.optimized(vec![stmt]);
+                backend(interpreter, stmt)
+            }
+            Err(e) => println!("{:?}", e),
+        }
+        if interpreter.exit_code.is_some() {
             break;
         }
-        run(0, line.into_bytes(), true, interpreter);
     }
 }
 
-fn run(idx: usize, source: Vec<u8>, repl: bool, interpreter: &mut Interpreter) {
-    let v: Vec<Token> = vec![];
-    let mut scanner = Scanner {
-        source,
-        list: v,
-        current: 0,
-        start: 0,
-        line: idx,
-    };
-    scanner.scan_tokens();
-    let mut parser = Parser {
-        tokens: scanner.list,
-        current: 0,
-    };
+/// Reads stdin one line at a time, printing the prompt whenever the scanner
+/// asks for more input. An empty line ends the session.
+struct Prompt {
+    line: Vec<u8>,
+    pos: usize,
+    closed: bool,
+}
 
-    match parser.parse() {
-        Ok(stmt) => {
-            interpreter.interpret(stmt);
+impl Prompt {
+    fn new() -> Self {
+        Prompt {
+            line: Vec::new(),
+            pos: 0,
+            closed: false,
         }
-        Err(e) => println!("{:?}", e),
-    };
+    }
+}
+
+impl Read for Prompt {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.line.len() && !self.closed {
+            print!(">");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            if line.trim().is_empty() {
+                self.closed = true;
+            }
+            self.line = line.into_bytes();
+            self.pos = 0;
+        }
+        if self.closed {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
 }
@@ -0,0 +1,93 @@
+use crate::{interpreter::Data, value::Str};
+use std::collections::HashMap;
+
+/// A hashable map key. Only strings, numbers, booleans and nil can be used
+/// as keys; numbers are stored by their bit pattern.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Number(u64),
+    Str(Str),
+    Bool(bool),
+    Nil,
+}
+
+impl Key {
+    pub fn from_data(value: &Data) -> Option<Key> {
+        match value {
+            // -0.0 and 0.0 compare equal, so they have to hash the same too.
+            Data::Number(num) if *num == 0.0 => Some(Key::Number(0f64.to_bits())),
+            Data::Number(num) => Some(Key::Number(num.to_bits())),
+            Data::Str(str) => Some(Key::Str(str.clone())),
+            Data::Bool(bool) => Some(Key::Bool(*bool)),
+            Data::Nil => Some(Key::Nil),
+            _ => None,
+        }
+    }
+
+    pub fn to_data(&self) -> Data {
+        match self {
+            Key::Number(bits) => Data::Number(f64::from_bits(*bits)),
+            Key::Str(str) => Data::Str(str.clone()),
+            Key::Bool(bool) => Data::Bool(*bool),
+            Key::Nil => Data::Nil,
+        }
+    }
+}
+
+/// A map that remembers insertion order, so printing a map or listing its
+/// keys gives the same result on every run.
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(Key, Data)>,
+    index: HashMap<Key, usize>,
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Data> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.index.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: Key, value: Data) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Data> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self.index.get_mut(key).unwrap() -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Data)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
@@ -0,0 +1,16 @@
+use crate::environment::Environment;
+use std::{cell::RefCell, fmt, path::PathBuf, rc::Rc};
+
+/// An imported file. Its top-level definitions live in `env` and are read
+/// through `name.member` expressions.
+pub struct Module {
+    pub name: String,
+    pub path: PathBuf,
+    pub env: Rc<RefCell<Environment>>,
+}
+
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
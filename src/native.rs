@@ -0,0 +1,266 @@
+use crate::{
+    environment::Environment,
+    gc::Object,
+    interpreter::{map_key, Data, Error, Interpreter, LoxCallable},
+    iterator::Range,
+    map::LoxMap,
+    native_io, native_list, native_math, native_string,
+    scanner::Span,
+    symbol::Symbol,
+    value::{Str, Value},
+};
+use std::{
+    cell::RefCell,
+    fmt,
+    ops::RangeInclusive,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub type NativeFn = fn(&mut Interpreter, &Span, Vec<Data>) -> Result<Data, Error>;
+
+/// A builtin implemented in Rust. `paren` is the closing parenthesis of the
+/// call so that errors raised by the builtin point at the calling line.
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: RangeInclusive<usize>,
+    pub function: NativeFn,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn arity(&self) -> RangeInclusive<usize> {
+        self.arity.clone()
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Span,
+        arguments: Vec<Data>,
+    ) -> Result<Data, Error> {
+        (self.function)(interpreter, paren, arguments)
+    }
+}
+
+/// Registers every builtin in the global environment.
+pub fn define_globals(env: &mut Environment) {
+    define(env, "clock", 0..=0, clock);
+    define(env, "len", 1..=1, len);
+    define(env, "push", 2..=2, push);
+    define(env, "pop", 1..=1, pop);
+    define(env, "keys", 1..=1, keys);
+    define(env, "values", 1..=1, values);
+    define(env, "has", 2..=2, has);
+    define(env, "remove", 2..=2, remove);
+    define(env, "range", 1..=3, range);
+    native_list::define_globals(env);
+    native_string::define_globals(env);
+    native_math::define_globals(env);
+    native_io::define_globals(env);
+}
+
+pub fn define(
+    env: &mut Environment,
+    name: &'static str,
+    arity: RangeInclusive<usize>,
+    function: NativeFn,
+) {
+    let native = NativeFunction {
+        name,
+        arity,
+        function,
+    };
+    env.define(Symbol::from_static(name), Data::Native(Rc::new(native)));
+}
+
+fn clock(_: &mut Interpreter, _: &Span, _: Vec<Data>) -> Result<Data, Error> {
+    let since_the_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    Ok(Data::Number(since_the_epoch.as_secs_f64()))
+}
+
+fn len(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    match &arguments[0] {
+        Data::List(list) => Ok(Data::Number(list.borrow().len() as f64)),
+        Data::Map(map) => Ok(Data::Number(map.borrow().len() as f64)),
+        Data::Range(range) => Ok(Data::Number(range.len() as f64)),
+        Data::Str(str) => Ok(Data::Number(str.chars().count() as f64)),
+        other => Err(native_error(
+            paren,
+            format!("len() expects a list, a map or a string, got {}.", other),
+        )),
+    }
+}
+
+fn push(_: &mut Interpreter, paren: &Span, mut arguments: Vec<Data>) -> Result<Data, Error> {
+    let value = arguments.pop().unwrap();
+    let list = expect_list(paren, "push", &arguments[0])?;
+    list.borrow_mut().push(value.into());
+    Ok(Data::Nil)
+}
+
+fn pop(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let list = expect_list(paren, "pop", &arguments[0])?;
+    let value = list.borrow_mut().pop().map(Value::into_data);
+    value.ok_or_else(|| native_error(paren, String::from("Can't pop from an empty list.")))
+}
+
+fn keys(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let map = expect_map(paren, "keys", &arguments[0])?;
+    let keys = map.borrow().iter().map(|(key, _)| key.to_data()).collect();
+    Ok(new_list(interpreter, keys))
+}
+
+fn values(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let map = expect_map(paren, "values", &arguments[0])?;
+    let values = map
+        .borrow()
+        .iter()
+        .map(|(_, value)| value.clone())
+        .collect();
+    Ok(new_list(interpreter, values))
+}
+
+fn has(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let map = expect_map(paren, "has", &arguments[0])?;
+    let key = map_key(paren, &arguments[1])?;
+    let found = map.borrow().contains_key(&key);
+    Ok(Data::Bool(found))
+}
+
+/// Removes `key` and returns its value, or nil if the key was absent.
+fn remove(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let map = expect_map(paren, "remove", &arguments[0])?;
+    let key = map_key(paren, &arguments[1])?;
+    let value = map.borrow_mut().remove(&key);
+    Ok(value.unwrap_or(Data::Nil))
+}
+
+/// `range(end)`, `range(start, end)` or `range(start, end, step)`. The end
+/// is exclusive.
+fn range(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let mut numbers = Vec::with_capacity(arguments.len());
+    for argument in &arguments {
+        numbers.push(expect_number(paren, "range", argument)?);
+    }
+    let range = match numbers[..] {
+        [end] => Range {
+            start: 0.0,
+            end,
+            step: 1.0,
+        },
+        [start, end] => Range {
+            start,
+            end,
+            step: 1.0,
+        },
+        [start, end, step] => Range { start, end, step },
+        _ => unreachable!(),
+    };
+    if range.step == 0.0 {
+        return Err(native_error(
+            paren,
+            String::from("range() step can't be zero."),
+        ));
+    }
+    Ok(Data::Range(range))
+}
+
+pub fn new_list<T: Into<Value>>(interpreter: &mut Interpreter, elements: Vec<T>) -> Data {
+    let elements = elements.into_iter().map(Into::into).collect();
+    let list = Rc::new(RefCell::new(elements));
+    interpreter.track(Object::List(list.clone()));
+    Data::List(list)
+}
+
+pub fn new_string(interpreter: &mut Interpreter, string: impl Into<Str>) -> Data {
+    let string = string.into();
+    interpreter.track(Object::Str(string.clone()));
+    Data::Str(string)
+}
+
+pub fn new_map(interpreter: &mut Interpreter, map: LoxMap) -> Data {
+    let map = Rc::new(RefCell::new(map));
+    interpreter.track(Object::Map(map.clone()));
+    Data::Map(map)
+}
+
+pub fn expect_list(
+    paren: &Span,
+    name: &str,
+    value: &Data,
+) -> Result<Rc<RefCell<Vec<Value>>>, Error> {
+    match value {
+        Data::List(list) => Ok(list.clone()),
+        other => Err(native_error(
+            paren,
+            format!("{}() expects a list, got {}.", name, other),
+        )),
+    }
+}
+
+pub fn expect_number(paren: &Span, name: &str, value: &Data) -> Result<f64, Error> {
+    match value {
+        Data::Number(num) => Ok(*num),
+        other => Err(native_error(
+            paren,
+            format!("{}() expects a number, got {}.", name, other),
+        )),
+    }
+}
+
+pub fn expect_string<'a>(paren: &Span, name: &str, value: &'a Data) -> Result<&'a str, Error> {
+    match value {
+        Data::Str(str) => Ok(str),
+        other => Err(native_error(
+            paren,
+            format!("{}() expects a string, got {}.", name, other),
+        )),
+    }
+}
+
+/// A non-negative integer argument such as a position or a count.
+pub fn expect_index(paren: &Span, name: &str, value: &Data) -> Result<usize, Error> {
+    match value {
+        Data::Number(num) if num.fract() == 0.0 && *num >= 0.0 => Ok(*num as usize),
+        other => Err(native_error(
+            paren,
+            format!("{}() expects a non-negative integer, got {}.", name, other),
+        )),
+    }
+}
+
+pub fn expect_integer(paren: &Span, name: &str, value: &Data) -> Result<i64, Error> {
+    match value {
+        Data::Number(num) if num.fract() == 0.0 && num.abs() < (1u64 << 53) as f64 => {
+            Ok(*num as i64)
+        }
+        other => Err(native_error(
+            paren,
+            format!("{}() expects an integer, got {}.", name, other),
+        )),
+    }
+}
+
+pub fn expect_map(paren: &Span, name: &str, value: &Data) -> Result<Rc<RefCell<LoxMap>>, Error> {
+    match value {
+        Data::Map(map) => Ok(map.clone()),
+        other => Err(native_error(
+            paren,
+            format!("{}() expects a map, got {}.", name, other),
+        )),
+    }
+}
+
+pub fn native_error(paren: &Span, message: String) -> Error {
+    Error::NativeError(*paren, message)
+}
@@ -0,0 +1,172 @@
+use crate::{
+    environment::Environment,
+    interpreter::{Data, Error, Interpreter},
+    native::{define, expect_integer, expect_string, native_error, new_list, new_string},
+    scanner::Span,
+};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, Write},
+};
+
+/// What a script is allowed to touch outside the interpreter. Builtins that
+/// need a missing capability fail with a runtime error instead of running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capabilities {
+    /// `read_file`, `write_file` and `append_file`.
+    pub filesystem: bool,
+    /// `env` and `args`.
+    pub environment: bool,
+    /// `read_line`.
+    pub stdin: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capabilities {
+            filesystem: true,
+            environment: true,
+            stdin: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Capabilities {
+            filesystem: false,
+            environment: false,
+            stdin: false,
+        }
+    }
+}
+
+pub fn define_globals(env: &mut Environment) {
+    define(env, "read_file", 1..=1, read_file);
+    define(env, "write_file", 2..=2, write_file);
+    define(env, "append_file", 2..=2, append_file);
+    define(env, "read_line", 0..=0, read_line);
+    define(env, "args", 0..=0, args);
+    define(env, "env", 1..=1, env_var);
+    define(env, "exit", 0..=1, exit);
+}
+
+fn require(paren: &Span, name: &str, allowed: bool, capability: &str) -> Result<(), Error> {
+    if allowed {
+        return Ok(());
+    }
+    Err(native_error(
+        paren,
+        format!("{}() needs the '{}' capability.", name, capability),
+    ))
+}
+
+fn io_error(paren: &Span, name: &str, path: &str, e: io::Error) -> Error {
+    native_error(paren, format!("{}() failed for '{}': {}", name, path, e))
+}
+
+fn read_file(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let filesystem = interpreter.capabilities.filesystem;
+    require(paren, "read_file", filesystem, "filesystem")?;
+    let path = expect_string(paren, "read_file", &arguments[0])?;
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(new_string(interpreter, contents)),
+        Err(e) => Err(io_error(paren, "read_file", path, e)),
+    }
+}
+
+fn write_file(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let filesystem = interpreter.capabilities.filesystem;
+    require(paren, "write_file", filesystem, "filesystem")?;
+    let path = expect_string(paren, "write_file", &arguments[0])?;
+    let contents = expect_string(paren, "write_file", &arguments[1])?;
+    match fs::write(path, contents) {
+        Ok(()) => Ok(Data::Nil),
+        Err(e) => Err(io_error(paren, "write_file", path, e)),
+    }
+}
+
+fn append_file(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let filesystem = interpreter.capabilities.filesystem;
+    require(paren, "append_file", filesystem, "filesystem")?;
+    let path = expect_string(paren, "append_file", &arguments[0])?;
+    let contents = expect_string(paren, "append_file", &arguments[1])?;
+    let result = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+    match result {
+        Ok(()) => Ok(Data::Nil),
+        Err(e) => Err(io_error(paren, "append_file", path, e)),
+    }
+}
+
+/// The next line of standard input without its line ending, or nil once
+/// input is exhausted.
+fn read_line(interpreter: &mut Interpreter, paren: &Span, _: Vec<Data>) -> Result<Data, Error> {
+    require(paren, "read_line", interpreter.capabilities.stdin, "stdin")?;
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) => Ok(Data::Nil),
+        Ok(_) => {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Ok(new_string(interpreter, line))
+        }
+        Err(e) => Err(io_error(paren, "read_line", "stdin", e)),
+    }
+}
+
+/// The command line arguments that followed the script path.
+fn args(interpreter: &mut Interpreter, paren: &Span, _: Vec<Data>) -> Result<Data, Error> {
+    let environment = interpreter.capabilities.environment;
+    require(paren, "args", environment, "environment")?;
+    let args = interpreter.args.clone();
+    let args = args
+        .into_iter()
+        .map(|arg| new_string(interpreter, arg))
+        .collect();
+    Ok(new_list(interpreter, args))
+}
+
+/// The value of an environment variable, or nil if it is unset.
+fn env_var(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let environment = interpreter.capabilities.environment;
+    require(paren, "env", environment, "environment")?;
+    let name = expect_string(paren, "env", &arguments[0])?;
+    match std::env::var(name) {
+        Ok(value) => Ok(new_string(interpreter, value)),
+        Err(_) => Ok(Data::Nil),
+    }
+}
+
+/// Stops the script. The process itself is left to the embedder, which can
+/// read the code from `Interpreter::exit_code`.
+fn exit(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let code = match arguments.first() {
+        Some(code) => expect_integer(paren, "exit", code)?,
+        None => 0,
+    };
+    match i32::try_from(code) {
+        Ok(code) => Err(Error::Exit(code)),
+        Err(_) => Err(native_error(
+            paren,
+            format!("exit() code {} is out of range.", code),
+        )),
+    }
+}
@@ -0,0 +1,143 @@
+//! Higher-order list builtins. Callbacks are called through
+//! `Interpreter::call`, so they can be natives, declared functions or
+//! function expressions, and errors they raise keep their own line. Values
+//! a builtin keeps while a callback runs are held for the collector.
+use crate::{
+    environment::Environment,
+    interpreter::{is_truthy, Data, Error, Interpreter},
+    native::{define, expect_list, native_error, new_list},
+    scanner::Span,
+    value::Value,
+};
+use std::cmp::Ordering;
+
+pub fn define_globals(env: &mut Environment) {
+    define(env, "map", 2..=2, map);
+    define(env, "filter", 2..=2, filter);
+    define(env, "reduce", 2..=3, reduce);
+    define(env, "sort_by", 2..=2, sort_by);
+    define(env, "any", 2..=2, any);
+    define(env, "all", 2..=2, all);
+}
+
+/// The list is copied first so the callback may modify the original.
+fn elements(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    name: &str,
+    value: &Data,
+) -> Result<Vec<Data>, Error> {
+    let elements: Vec<Data> = expect_list(paren, name, value)?
+        .borrow()
+        .iter()
+        .map(Value::get)
+        .collect();
+    for element in &elements {
+        interpreter.hold(element.clone());
+    }
+    Ok(elements)
+}
+
+fn map(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let mut mapped = Vec::new();
+    for element in elements(interpreter, paren, "map", &arguments[0])? {
+        let value = interpreter.call(paren, &arguments[1], vec![element])?;
+        interpreter.hold(value.clone());
+        mapped.push(value);
+    }
+    Ok(new_list(interpreter, mapped))
+}
+
+fn filter(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let mut kept = Vec::new();
+    for element in elements(interpreter, paren, "filter", &arguments[0])? {
+        if is_truthy(interpreter.call(paren, &arguments[1], vec![element.clone()])?) {
+            kept.push(element);
+        }
+    }
+    Ok(new_list(interpreter, kept))
+}
+
+/// `reduce(list, f, initial)`. Without `initial` the first element is used,
+/// which makes reducing an empty list an error.
+fn reduce(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    mut arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let initial = if arguments.len() == 3 {
+        arguments.pop()
+    } else {
+        None
+    };
+    let mut elements = elements(interpreter, paren, "reduce", &arguments[0])?.into_iter();
+    let mut accumulator = match initial.or_else(|| elements.next()) {
+        Some(value) => value,
+        None => {
+            return Err(native_error(
+                paren,
+                String::from("Can't reduce an empty list without an initial value."),
+            ))
+        }
+    };
+    for element in elements {
+        accumulator = interpreter.call(paren, &arguments[1], vec![accumulator, element])?;
+        interpreter.hold(accumulator.clone());
+    }
+    Ok(accumulator)
+}
+
+/// Returns a new list ordered by the key the callback computes for each
+/// element. Keys must be all numbers or all strings; equal keys keep their
+/// order.
+fn sort_by(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let mut keyed = Vec::new();
+    for element in elements(interpreter, paren, "sort_by", &arguments[0])? {
+        let key = interpreter.call(paren, &arguments[1], vec![element.clone()])?;
+        interpreter.hold(key.clone());
+        keyed.push((key, element));
+    }
+    let comparable = keyed.iter().all(|(key, _)| matches!(key, Data::Number(_)))
+        || keyed.iter().all(|(key, _)| matches!(key, Data::Str(_)));
+    if !comparable {
+        return Err(native_error(
+            paren,
+            String::from("sort_by() keys must be all numbers or all strings."),
+        ));
+    }
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Data::Number(a), Data::Number(b)) => a.total_cmp(b),
+        (Data::Str(a), Data::Str(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    });
+    let sorted = keyed.into_iter().map(|(_, element)| element).collect();
+    Ok(new_list(interpreter, sorted))
+}
+
+/// Stops at the first element the callback accepts.
+fn any(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    for element in elements(interpreter, paren, "any", &arguments[0])? {
+        if is_truthy(interpreter.call(paren, &arguments[1], vec![element])?) {
+            return Ok(Data::Bool(true));
+        }
+    }
+    Ok(Data::Bool(false))
+}
+
+/// Stops at the first element the callback rejects.
+fn all(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    for element in elements(interpreter, paren, "all", &arguments[0])? {
+        if !is_truthy(interpreter.call(paren, &arguments[1], vec![element])?) {
+            return Ok(Data::Bool(false));
+        }
+    }
+    Ok(Data::Bool(true))
+}
@@ -0,0 +1,178 @@
+use crate::{
+    environment::Environment,
+    interpreter::{Data, Error, Interpreter},
+    native::{define, expect_integer, expect_number, native_error},
+    scanner::Span,
+    symbol::Symbol,
+    value::Value,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A small SplitMix64 generator. It is fully determined by its seed, so
+/// scripts run with a fixed `--seed` produce the same numbers every time.
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    /// Seeds from the system clock.
+    pub fn from_time() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Random::new(now.as_nanos() as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub fn define_globals(env: &mut Environment) {
+    env.define(Symbol::from_static("PI"), Data::Number(std::f64::consts::PI));
+    env.define(Symbol::from_static("E"), Data::Number(std::f64::consts::E));
+
+    define(env, "sqrt", 1..=1, sqrt);
+    define(env, "pow", 2..=2, pow);
+    define(env, "abs", 1..=1, abs);
+    define(env, "floor", 1..=1, floor);
+    define(env, "ceil", 1..=1, ceil);
+    define(env, "round", 1..=1, round);
+    define(env, "min", 1..=255, min);
+    define(env, "max", 1..=255, max);
+    define(env, "sin", 1..=1, sin);
+    define(env, "cos", 1..=1, cos);
+    define(env, "tan", 1..=1, tan);
+    define(env, "log", 1..=1, log);
+    define(env, "exp", 1..=1, exp);
+    define(env, "random", 0..=0, random);
+    define(env, "random_int", 2..=2, random_int);
+    define(env, "seed", 1..=1, seed);
+}
+
+fn unary(paren: &Span, name: &str, arguments: &[Data], f: fn(f64) -> f64) -> Result<Data, Error> {
+    Ok(Data::Number(f(expect_number(paren, name, &arguments[0])?)))
+}
+
+fn sqrt(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "sqrt", &arguments, f64::sqrt)
+}
+
+fn pow(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let base = expect_number(paren, "pow", &arguments[0])?;
+    let exponent = expect_number(paren, "pow", &arguments[1])?;
+    Ok(Data::Number(base.powf(exponent)))
+}
+
+fn abs(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "abs", &arguments, f64::abs)
+}
+
+fn floor(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "floor", &arguments, f64::floor)
+}
+
+fn ceil(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "ceil", &arguments, f64::ceil)
+}
+
+/// Rounds half away from zero.
+fn round(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "round", &arguments, f64::round)
+}
+
+/// The numbers to compare are either the arguments themselves or the
+/// elements of a single list argument.
+fn numbers(paren: &Span, name: &str, arguments: &[Data]) -> Result<Vec<f64>, Error> {
+    let values = match arguments {
+        [Data::List(list)] => list.borrow().iter().map(Value::get).collect(),
+        _ => arguments.to_vec(),
+    };
+    if values.is_empty() {
+        return Err(native_error(
+            paren,
+            format!("{}() needs at least one number.", name),
+        ));
+    }
+    values
+        .iter()
+        .map(|value| expect_number(paren, name, value))
+        .collect()
+}
+
+fn min(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let numbers = numbers(paren, "min", &arguments)?;
+    Ok(Data::Number(
+        numbers.into_iter().fold(f64::INFINITY, f64::min),
+    ))
+}
+
+fn max(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let numbers = numbers(paren, "max", &arguments)?;
+    Ok(Data::Number(
+        numbers.into_iter().fold(f64::NEG_INFINITY, f64::max),
+    ))
+}
+
+fn sin(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "sin", &arguments, f64::sin)
+}
+
+fn cos(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "cos", &arguments, f64::cos)
+}
+
+fn tan(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "tan", &arguments, f64::tan)
+}
+
+/// The natural logarithm.
+fn log(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "log", &arguments, f64::ln)
+}
+
+fn exp(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    unary(paren, "exp", &arguments, f64::exp)
+}
+
+/// A number in `[0, 1)`.
+fn random(interpreter: &mut Interpreter, _: &Span, _: Vec<Data>) -> Result<Data, Error> {
+    Ok(Data::Number(interpreter.rng.next_f64()))
+}
+
+/// An integer between `low` and `high`, both inclusive.
+fn random_int(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let low = expect_integer(paren, "random_int", &arguments[0])?;
+    let high = expect_integer(paren, "random_int", &arguments[1])?;
+    if low > high {
+        return Err(native_error(
+            paren,
+            format!("random_int() range {} to {} is empty.", low, high),
+        ));
+    }
+    let span = (high - low) as u64 + 1;
+    let offset = interpreter.rng.next_u64() % span;
+    Ok(Data::Number((low + offset as i64) as f64))
+}
+
+fn seed(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let seed = expect_integer(paren, "seed", &arguments[0])?;
+    interpreter.rng = Random::new(seed as u64);
+    Ok(Data::Nil)
+}
@@ -0,0 +1,190 @@
+use crate::{
+    environment::Environment,
+    interpreter::{Data, Error, Interpreter},
+    native::{
+        define, expect_index, expect_list, expect_string, native_error, new_list, new_string,
+    },
+    scanner::Span,
+};
+
+/// String builtins. Positions and lengths count characters, not bytes.
+pub fn define_globals(env: &mut Environment) {
+    define(env, "substr", 2..=3, substr);
+    define(env, "index_of", 2..=2, index_of);
+    define(env, "split", 2..=2, split);
+    define(env, "join", 2..=2, join);
+    define(env, "trim", 1..=1, trim);
+    define(env, "upper", 1..=1, upper);
+    define(env, "lower", 1..=1, lower);
+    define(env, "replace", 3..=3, replace);
+    define(env, "starts_with", 2..=2, starts_with);
+    define(env, "ends_with", 2..=2, ends_with);
+    define(env, "char_at", 2..=2, char_at);
+    define(env, "ord", 1..=1, ord);
+    define(env, "chr", 1..=1, chr);
+    define(env, "str", 1..=1, str);
+    define(env, "num", 1..=1, num);
+}
+
+/// `substr(s, start)` or `substr(s, start, length)`. The result is cut short
+/// at the end of the string.
+fn substr(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let s = expect_string(paren, "substr", &arguments[0])?;
+    let start = expect_index(paren, "substr", &arguments[1])?;
+    let length = match arguments.get(2) {
+        Some(length) => expect_index(paren, "substr", length)?,
+        None => usize::MAX,
+    };
+    let count = s.chars().count();
+    if start > count {
+        return Err(native_error(
+            paren,
+            format!("substr() start {} is past the end of the string.", start),
+        ));
+    }
+    let substr: String = s.chars().skip(start).take(length).collect();
+    Ok(new_string(interpreter, substr))
+}
+
+/// The character position of the first occurrence of `needle`, or -1.
+fn index_of(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let s = expect_string(paren, "index_of", &arguments[0])?;
+    let needle = expect_string(paren, "index_of", &arguments[1])?;
+    let index = match s.find(needle) {
+        Some(byte) => s[..byte].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(Data::Number(index))
+}
+
+/// Splitting on the empty string yields the individual characters.
+fn split(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let s = expect_string(paren, "split", &arguments[0])?;
+    let separator = expect_string(paren, "split", &arguments[1])?;
+    let parts = if separator.is_empty() {
+        s.chars()
+            .map(|c| new_string(interpreter, c.to_string()))
+            .collect()
+    } else {
+        s.split(separator)
+            .map(|part| new_string(interpreter, part))
+            .collect()
+    };
+    Ok(new_list(interpreter, parts))
+}
+
+fn join(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let list = expect_list(paren, "join", &arguments[0])?;
+    let separator = expect_string(paren, "join", &arguments[1])?;
+    let parts: Vec<String> = list.borrow().iter().map(|part| part.to_string()).collect();
+    Ok(new_string(interpreter, parts.join(separator)))
+}
+
+fn trim(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let s = expect_string(paren, "trim", &arguments[0])?;
+    Ok(new_string(interpreter, s.trim()))
+}
+
+fn upper(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let s = expect_string(paren, "upper", &arguments[0])?;
+    Ok(new_string(interpreter, s.to_uppercase()))
+}
+
+fn lower(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let s = expect_string(paren, "lower", &arguments[0])?;
+    Ok(new_string(interpreter, s.to_lowercase()))
+}
+
+/// Replaces every occurrence of `from` with `to`.
+fn replace(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let s = expect_string(paren, "replace", &arguments[0])?;
+    let from = expect_string(paren, "replace", &arguments[1])?;
+    let to = expect_string(paren, "replace", &arguments[2])?;
+    if from.is_empty() {
+        return Err(native_error(
+            paren,
+            String::from("replace() can't replace the empty string."),
+        ));
+    }
+    Ok(new_string(interpreter, s.replace(from, to)))
+}
+
+fn starts_with(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let s = expect_string(paren, "starts_with", &arguments[0])?;
+    let prefix = expect_string(paren, "starts_with", &arguments[1])?;
+    Ok(Data::Bool(s.starts_with(prefix)))
+}
+
+fn ends_with(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let s = expect_string(paren, "ends_with", &arguments[0])?;
+    let suffix = expect_string(paren, "ends_with", &arguments[1])?;
+    Ok(Data::Bool(s.ends_with(suffix)))
+}
+
+fn char_at(
+    interpreter: &mut Interpreter,
+    paren: &Span,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let s = expect_string(paren, "char_at", &arguments[0])?;
+    let index = expect_index(paren, "char_at", &arguments[1])?;
+    match s.chars().nth(index) {
+        Some(c) => Ok(new_string(interpreter, c.to_string())),
+        None => Err(native_error(
+            paren,
+            format!("char_at() index {} is out of range.", index),
+        )),
+    }
+}
+
+/// The Unicode code point of a one-character string.
+fn ord(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let s = expect_string(paren, "ord", &arguments[0])?;
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Data::Number(c as u32 as f64)),
+        _ => Err(native_error(
+            paren,
+            format!("ord() expects a single character, got '{}'.", s),
+        )),
+    }
+}
+
+fn chr(interpreter: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    let code = expect_index(paren, "chr", &arguments[0])?;
+    match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(c) => Ok(new_string(interpreter, c.to_string())),
+        None => Err(native_error(
+            paren,
+            format!("chr() got an invalid code point {}.", code),
+        )),
+    }
+}
+
+fn str(interpreter: &mut Interpreter, _: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    Ok(new_string(interpreter, arguments[0].to_string()))
+}
+
+/// Parses a number, ignoring surrounding whitespace. Numbers are returned
+/// unchanged.
+fn num(_: &mut Interpreter, paren: &Span, arguments: Vec<Data>) -> Result<Data, Error> {
+    if let Data::Number(num) = arguments[0] {
+        return Ok(Data::Number(num));
+    }
+    let s = expect_string(paren, "num", &arguments[0])?;
+    match s.trim().parse::<f64>() {
+        Ok(num) => Ok(Data::Number(num)),
+        Err(_) => Err(native_error(
+            paren,
+            format!("num() can't parse '{}' as a number.", s),
+        )),
+    }
+}
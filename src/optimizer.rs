@@ -0,0 +1,320 @@
+//! An optional pass over the syntax tree between parsing and running it.
+//!
+//! Operators whose operands are all literals are evaluated ahead of time,
+//! `if` statements and conditional expressions with a literal condition are
+//! replaced by the branch that would run, and loops whose condition is a
+//! falsey literal are dropped. An operation is folded only when evaluating
+//! it succeeds, so everything that can still fail at runtime is left in the
+//! tree with its span and reports the same line it would have without the
+//! pass.
+
+use crate::{
+    expr::{
+        Assign, Binary, Call, Conditional, Expr, Get, Index, List, Logical, Map, SetIndex, Unary,
+    },
+    interpreter::{binary_op, is_truthy, Data},
+    scanner::{Literal, TokenType},
+    stmt::{Block, Catch, ForIn, Function, If, Stmt, Try, While},
+};
+use std::rc::Rc;
+
+/// Optimizes a script, a module or a single statement typed into the REPL.
+/// Must run before the resolver, which records where variables live in the
+/// tree it is given.
+pub fn optimize(statements: Vec<Stmt>) -> Vec<Stmt> {
+    statements.into_iter().filter_map(statement).collect()
+}
+
+/// Returns `None` for a statement that does nothing.
+fn statement(stmt: Stmt) -> Option<Stmt> {
+    let stmt = match stmt {
+        Stmt::Block(block) => Stmt::Block(Block {
+            statements: optimize(block.statements),
+        }),
+        Stmt::Expression(mut stmt) => {
+            stmt.expression = expression(stmt.expression);
+            Stmt::Expression(stmt)
+        }
+        Stmt::Print(mut stmt) => {
+            stmt.expression = expression(stmt.expression);
+            Stmt::Print(stmt)
+        }
+        Stmt::Var(mut stmt) => {
+            stmt.initializer = stmt.initializer.map(expression);
+            Stmt::Var(stmt)
+        }
+        Stmt::If(stmt) => {
+            let condition = expression(stmt.condition);
+            if let Some(truthy) = truthiness(&condition) {
+                // A branch is never a declaration, so it can take the place of
+                // the `if` without changing any scope.
+                return match (truthy, stmt.else_branch) {
+                    (true, _) => statement(*stmt.then_branch),
+                    (false, Some(else_branch)) => statement(*else_branch),
+                    (false, None) => None,
+                };
+            }
+            Stmt::If(If {
+                condition,
+                then_branch: Box::new(body(*stmt.then_branch)),
+                else_branch: stmt.else_branch.map(|branch| Box::new(body(*branch))),
+            })
+        }
+        Stmt::While(stmt) => {
+            let While {
+                condition,
+                body: loop_body,
+                increment,
+            } = *stmt;
+            let condition = expression(condition);
+            if truthiness(&condition) == Some(false) {
+                return None;
+            }
+            Stmt::While(Box::new(While {
+                condition,
+                body: body(loop_body),
+                increment: increment.map(expression),
+            }))
+        }
+        Stmt::ForIn(stmt) => {
+            let ForIn {
+                name,
+                keyword,
+                iterable,
+                body: loop_body,
+                slot,
+            } = *stmt;
+            Stmt::ForIn(Box::new(ForIn {
+                name,
+                keyword,
+                iterable: expression(iterable),
+                body: body(loop_body),
+                slot,
+            }))
+        }
+        Stmt::Function(function) => Stmt::Function(self::function(function)),
+        Stmt::Return(mut stmt) => {
+            stmt.value = stmt.value.map(expression);
+            Stmt::Return(stmt)
+        }
+        Stmt::Throw(mut stmt) => {
+            stmt.value = expression(stmt.value);
+            Stmt::Throw(stmt)
+        }
+        Stmt::Try(stmt) => {
+            let Try {
+                body,
+                catch,
+                finally,
+            } = *stmt;
+            Stmt::Try(Box::new(Try {
+                body: optimize(body),
+                catch: catch.map(|catch| Catch {
+                    body: optimize(catch.body),
+                    ..catch
+                }),
+                finally: finally.map(optimize),
+            }))
+        }
+        stmt @ (Stmt::Break(_) | Stmt::Continue(_) | Stmt::Import(_)) => stmt,
+    };
+    Some(stmt)
+}
+
+/// A statement that has to stay, such as the body of a loop. One that does
+/// nothing becomes an empty block.
+fn body(stmt: Stmt) -> Stmt {
+    statement(stmt).unwrap_or(Stmt::Block(Block {
+        statements: Vec::new(),
+    }))
+}
+
+fn function(function: Rc<Function>) -> Rc<Function> {
+    // Nothing else holds a function before the tree runs.
+    let function = Rc::try_unwrap(function).unwrap_or_else(|shared| (*shared).clone());
+    Rc::new(Function {
+        body: optimize(function.body),
+        ..function
+    })
+}
+
+fn expression(expr: Expr) -> Expr {
+    match expr {
+        Expr::Literal(_) | Expr::Variable(_) => expr,
+        Expr::Grouping(mut grouping) => {
+            grouping.expression = expression(grouping.expression);
+            match grouping.expression {
+                Expr::Literal(literal) => Expr::Literal(literal),
+                _ => Expr::Grouping(grouping),
+            }
+        }
+        Expr::Unary(unary) => {
+            let Unary { operator, right } = *unary;
+            let right = expression(right);
+            let folded = constant(&right).and_then(|right| match (operator.ttype, right) {
+                (TokenType::Minus, Data::Number(right)) => Some(Literal::Number(-right)),
+                (TokenType::Bang, right) => Some(Literal::Bool(!is_truthy(right))),
+                _ => None,
+            });
+            match folded {
+                Some(literal) => Expr::Literal(literal),
+                None => Expr::Unary(Box::new(Unary { operator, right })),
+            }
+        }
+        Expr::Binary(binary) => {
+            let Binary {
+                left,
+                operator,
+                right,
+            } = *binary;
+            let (left, right) = (expression(left), expression(right));
+            let folded = match (constant(&left), constant(&right)) {
+                (Some(a), Some(b)) => binary_op(&operator, a, b).ok().and_then(literal),
+                _ => None,
+            };
+            match folded {
+                Some(literal) => Expr::Literal(literal),
+                None => Expr::Binary(Box::new(Binary {
+                    left,
+                    operator,
+                    right,
+                })),
+            }
+        }
+        Expr::Logical(logical) => {
+            let Logical {
+                left,
+                operator,
+                right,
+            } = *logical;
+            let left = expression(left);
+            let right = expression(right);
+            match truthiness(&left) {
+                // `or` stops at a truthy operand and `and` at a falsey one.
+                Some(truthy) if truthy == (operator.ttype == TokenType::Or) => left,
+                Some(_) => right,
+                None => Expr::Logical(Box::new(Logical {
+                    left,
+                    operator,
+                    right,
+                })),
+            }
+        }
+        Expr::Conditional(conditional) => {
+            let Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } = *conditional;
+            let condition = expression(condition);
+            match truthiness(&condition) {
+                Some(true) => expression(then_branch),
+                Some(false) => expression(else_branch),
+                None => Expr::Conditional(Box::new(Conditional {
+                    condition,
+                    then_branch: expression(then_branch),
+                    else_branch: expression(else_branch),
+                })),
+            }
+        }
+        Expr::Assign(assign) => {
+            let Assign { name, value, slot } = *assign;
+            Expr::Assign(Box::new(Assign {
+                name,
+                value: expression(value),
+                slot,
+            }))
+        }
+        Expr::Call(call) => {
+            let Call {
+                callee,
+                paren,
+                arguments,
+            } = *call;
+            Expr::Call(Box::new(Call {
+                callee: expression(callee),
+                paren,
+                arguments: arguments.into_iter().map(expression).collect(),
+            }))
+        }
+        Expr::List(list) => {
+            let List { bracket, elements } = *list;
+            Expr::List(Box::new(List {
+                bracket,
+                elements: elements.into_iter().map(expression).collect(),
+            }))
+        }
+        Expr::Map(map) => {
+            let Map { brace, entries } = *map;
+            Expr::Map(Box::new(Map {
+                brace,
+                entries: entries
+                    .into_iter()
+                    .map(|(key, value)| (expression(key), expression(value)))
+                    .collect(),
+            }))
+        }
+        Expr::Index(index) => {
+            let Index {
+                object,
+                bracket,
+                index,
+            } = *index;
+            Expr::Index(Box::new(Index {
+                object: expression(object),
+                bracket,
+                index: expression(index),
+            }))
+        }
+        Expr::SetIndex(set) => {
+            let SetIndex {
+                object,
+                bracket,
+                index,
+                operator,
+                value,
+            } = *set;
+            Expr::SetIndex(Box::new(SetIndex {
+                object: expression(object),
+                bracket,
+                index: expression(index),
+                operator,
+                value: expression(value),
+            }))
+        }
+        Expr::Get(get) => {
+            let Get { object, name } = *get;
+            Expr::Get(Box::new(Get {
+                object: expression(object),
+                name,
+            }))
+        }
+        Expr::Function(function) => Expr::Function(self::function(function)),
+    }
+}
+
+/// The value of a literal expression.
+fn constant(expr: &Expr) -> Option<Data> {
+    match expr {
+        Expr::Literal(Literal::Number(number)) => Some(Data::Number(*number)),
+        Expr::Literal(Literal::Str(str)) => Some(Data::Str(str.clone())),
+        Expr::Literal(Literal::Bool(bool)) => Some(Data::Bool(*bool)),
+        Expr::Literal(Literal::Nil) => Some(Data::Nil),
+        _ => None,
+    }
+}
+
+fn truthiness(expr: &Expr) -> Option<bool> {
+    constant(expr).map(is_truthy)
+}
+
+/// The literal for a folded value.
+fn literal(value: Data) -> Option<Literal> {
+    match value {
+        Data::Number(number) => Some(Literal::Number(number)),
+        Data::Str(str) => Some(Literal::Str(str)),
+        Data::Bool(bool) => Some(Literal::Bool(bool)),
+        Data::Nil => Some(Literal::Nil),
+        _ => None,
+    }
+}
@@ -1,28 +1,54 @@
 use crate::expr::Assign;
 use crate::expr::Binary;
 use crate::expr::Call;
+use crate::expr::Conditional;
 use crate::expr::Expr;
+use crate::expr::Get;
 use crate::expr::Grouping;
+use crate::expr::Index;
+use crate::expr::List;
 use crate::expr::Logical;
+use crate::expr::Map;
+use crate::expr::SetIndex;
 use crate::expr::Unary;
 use crate::expr::Variable;
 use crate::scanner;
 use crate::scanner::Literal;
+use crate::scanner::Span;
 use crate::scanner::Token;
 use crate::scanner::TokenType;
 use crate::stmt::Block;
+use crate::stmt::Break;
+use crate::stmt::Catch;
+use crate::stmt::Continue;
 use crate::stmt::Expression;
+use crate::stmt::ForIn;
 use crate::stmt::If;
+use crate::stmt::Import;
 use crate::stmt::Print;
+use crate::stmt::Return;
 use crate::stmt::Stmt;
+use crate::stmt::Throw;
+use crate::stmt::Try;
 use crate::stmt::Var;
 use crate::stmt::While;
 use crate::stmt::Function;
+use crate::symbol::Symbol;
+use std::collections::VecDeque;
+use std::rc::Rc;
 
+/// Pulls tokens from any token stream on demand, buffering only as many as
+/// the grammar needs to look ahead.
 #[derive(Debug)]
-pub struct Parser {
-    pub tokens: Vec<Token>,
-    pub current: usize,
+pub struct Parser<I: Iterator<Item = Result<Token, scanner::Error>>> {
+    tokens: I,
+    lookahead: VecDeque<Token>,
+    previous: Option<Token>,
+    loop_depth: usize,
+    function_depth: usize,
+    /// Set once the scanner reports an error. Parsing carries on past it to
+    /// find more errors, but nothing parsed since is run.
+    scan_error: bool,
 }
 
 #[derive(Debug)]
@@ -30,19 +56,35 @@ pub enum Error {
     ParseError,
 }
 
-impl Parser {
+impl<I: Iterator<Item = Result<Token, scanner::Error>>> Parser<I> {
+    pub fn new(tokens: I) -> Self {
+        Parser {
+            tokens,
+            lookahead: VecDeque::new(),
+            previous: None,
+            loop_depth: 0,
+            function_depth: 0,
+            scan_error: false,
+        }
+    }
+
     fn expression(&mut self) -> Result<Expr, Error> {
         self.assignment()
     }
 
     fn declaration(&mut self) -> Result<Stmt, Error> {
-        if self.matching(&[TokenType::Fun]) {
-            return Ok(self.function("function".to_string()))?;
+        // Without a name, `fun` starts a function expression instead.
+        if self.check(&TokenType::Fun) && self.check_nth(1, &TokenType::Identifier) {
+            self.advance();
+            return self.function("function");
         }
         if self.matching(&[TokenType::Var]) {
-            return Ok(self.var_declaration())?;
+            return self.var_declaration();
         }
-        return Ok(self.statement())?;
+        if self.matching(&[TokenType::Import]) {
+            return self.import_declaration();
+        }
+        self.statement()
         // TODO: check that synchronize works
     }
 
@@ -59,26 +101,42 @@ impl Parser {
         if self.matching(&[TokenType::While]) {
             return self.while_statement();
         }
+        if self.matching(&[TokenType::Break]) {
+            return self.break_statement();
+        }
+        if self.matching(&[TokenType::Continue]) {
+            return self.continue_statement();
+        }
+        if self.matching(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+        if self.matching(&[TokenType::Throw]) {
+            return self.throw_statement();
+        }
+        if self.matching(&[TokenType::Try]) {
+            return self.try_statement();
+        }
 
         if self.matching(&[TokenType::LeftBrace]) {
-            let statements = self.block();
+            let statements = self.block()?;
             return Ok(Stmt::Block(Block { statements }));
         }
         self.expression_statement()
     }
 
     fn for_statement(&mut self) -> Result<Stmt, Error> {
-        // TODO: Make this nice
         self.consume(&TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        if self.check(&TokenType::Var) && self.check_nth(2, &TokenType::In) {
+            return self.for_in_statement();
+        }
 
-        let mut initializer = None;
-        if self.matching(&[TokenType::Semicolon]) {
-            initializer = None;
+        let initializer = if self.matching(&[TokenType::Semicolon]) {
+            None
         } else if self.matching(&[TokenType::Var]) {
-            initializer = Some(self.var_declaration()?);
+            Some(self.var_declaration()?)
         } else {
-            initializer = Some(self.expression_statement()?);
-        }
+            Some(self.expression_statement()?)
+        };
 
         let mut condition = None;
         if !self.check(&TokenType::Semicolon) {
@@ -92,33 +150,115 @@ impl Parser {
         }
         self.consume(&TokenType::RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
+        let body = self.loop_body()?;
+        let condition = condition.unwrap_or(Expr::Literal(Literal::Bool(true)));
+        let body = Stmt::While(Box::new(While {
+            condition,
+            body,
+            increment,
+        }));
+
+        Ok(match initializer {
+            Some(initializer) => Stmt::Block(Block {
+                statements: vec![initializer, body],
+            }),
+            None => body,
+        })
+    }
 
-        if increment.is_some() {
-            let b = vec![
-                body,
-                Stmt::Expression(Expression {
-                    expression: increment.unwrap(),
-                }),
-            ];
-            body = Stmt::Block(Block { statements: b });
+    fn for_in_statement(&mut self) -> Result<Stmt, Error> {
+        self.advance();
+        let name = self.consume(&TokenType::Identifier, "Expect variable name.")?;
+        let keyword = self.advance();
+        let iterable = self.expression()?;
+        self.consume(&TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = self.loop_body()?;
+        Ok(Stmt::ForIn(Box::new(ForIn {
+            name,
+            keyword,
+            iterable,
+            body,
+            slot: Default::default(),
+        })))
+    }
+
+    fn loop_body(&mut self) -> Result<Stmt, Error> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn break_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().span();
+        if self.loop_depth == 0 {
+            return Err(self.error(keyword, "Can't use 'break' outside of a loop."));
         }
-        if condition.is_none() {
-            condition = Some(Expr::Literal(Literal::Str(String::from("true"))));
+        self.consume(&TokenType::Semicolon, "Expect ';' after 'break'.")?;
+        Ok(Stmt::Break(Break { keyword }))
+    }
+
+    fn continue_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().span();
+        if self.loop_depth == 0 {
+            return Err(self.error(keyword, "Can't use 'continue' outside of a loop."));
         }
-        body = Stmt::While(Box::new(While {
-            condition: condition.unwrap(),
-            body,
-        }));
+        self.consume(&TokenType::Semicolon, "Expect ';' after 'continue'.")?;
+        Ok(Stmt::Continue(Continue { keyword }))
+    }
 
-        let mut s = vec![];
-        if initializer.is_some() {
-            s.push(initializer.unwrap());
-            s.push(body);
+    fn return_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().span();
+        if self.function_depth == 0 {
+            return Err(self.error(keyword, "Can't return from top-level code."));
+        }
+        let mut value = None;
+        if !self.check(&TokenType::Semicolon) {
+            value = Some(self.expression()?);
         }
+        self.consume(&TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return(Return { keyword, value }))
+    }
 
-        body = Stmt::Block(Block { statements: s });
-        Ok(body)
+    fn throw_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().span();
+        let value = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after thrown value.")?;
+        Ok(Stmt::Throw(Throw { keyword, value }))
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().span();
+        self.consume(&TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+
+        let mut catch = None;
+        if self.matching(&[TokenType::Catch]) {
+            self.consume(&TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume(&TokenType::Identifier, "Expect error variable name.")?;
+            self.consume(&TokenType::RightParen, "Expect ')' after error variable.")?;
+            self.consume(&TokenType::LeftBrace, "Expect '{' before catch body.")?;
+            catch = Some(Catch {
+                name,
+                body: self.block()?,
+                slot: Default::default(),
+            });
+        }
+        let mut finally = None;
+        if self.matching(&[TokenType::Finally]) {
+            self.consume(&TokenType::LeftBrace, "Expect '{' after 'finally'.")?;
+            finally = Some(self.block()?);
+        }
+
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error(keyword, "Expect 'catch' or 'finally' after try block."));
+        }
+        Ok(Stmt::Try(Box::new(Try {
+            body,
+            catch,
+            finally,
+        })))
     }
 
     fn if_statement(&mut self) -> Result<Stmt, Error> {
@@ -147,31 +287,50 @@ impl Parser {
     }
 
     fn var_declaration(&mut self) -> Result<Stmt, Error> {
-        let name = self
-            .consume(&TokenType::Identifier, "Expect variable name.")
-            .unwrap();
+        let name = self.consume(&TokenType::Identifier, "Expect variable name.")?;
 
         let mut initializer: Option<Expr> = None;
         if self.matching(&[TokenType::Equal]) {
-            match self.expression() {
-                Ok(init) => initializer = Some(init),
-                _ => (),
-            };
+            initializer = Some(self.expression()?);
         }
 
         self.consume(
             &TokenType::Semicolon,
             "Expect ';' after variable declaration.",
         )?;
-        Ok(Stmt::Var(Var { name, initializer }))
+        Ok(Stmt::Var(Var {
+            name,
+            initializer,
+            slot: Default::default(),
+        }))
+    }
+
+    fn import_declaration(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous().span();
+        let path = self.consume(&TokenType::String, "Expect module path after 'import'.")?;
+        let mut name = None;
+        if self.matching(&[TokenType::As]) {
+            name = Some(self.consume(&TokenType::Identifier, "Expect module name after 'as'.")?);
+        }
+        self.consume(&TokenType::Semicolon, "Expect ';' after import.")?;
+        Ok(Stmt::Import(Import {
+            keyword,
+            path,
+            name,
+            slot: Default::default(),
+        }))
     }
 
     fn while_statement(&mut self) -> Result<Stmt, Error> {
         self.consume(&TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(&TokenType::RightParen, "Expect ')' after condition.")?;
-        let body = self.statement()?;
-        Ok(Stmt::While(Box::new(While { condition, body })))
+        let body = self.loop_body()?;
+        Ok(Stmt::While(Box::new(While {
+            condition,
+            body,
+            increment: None,
+        })))
     }
 
     fn expression_statement(&mut self) -> Result<Stmt, Error> {
@@ -180,14 +339,24 @@ impl Parser {
         Ok(Stmt::Expression(Expression { expression: expr }))
     }
 
-    fn function(&mut self, kind: String) -> Result<Stmt, Error> {
-        let name = self.consume(&TokenType::Identifier, &("Expect ".to_string() + &kind + " name."))?;
-        self.consume(&TokenType::LeftParen, &("Expect '(' after ".to_string() + &kind + " name."))?;
-        let mut parameters: Vec<Token> = Vec::new();
+    fn function(&mut self, kind: &str) -> Result<Stmt, Error> {
+        let name = self.consume(&TokenType::Identifier, &format!("Expect {} name.", kind))?;
+        self.consume(
+            &TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+        let function = self.function_body(kind, Some(name))?;
+        Ok(Stmt::Function(Rc::new(function)))
+    }
+
+    /// Parses the parameters and body of a function whose opening parenthesis
+    /// has already been consumed.
+    fn function_body(&mut self, kind: &str, name: Option<Span>) -> Result<Function, Error> {
+        let mut parameters: Vec<Span> = Vec::new();
         if !self.check(&TokenType::RightParen) {
             loop {
                 if parameters.len() >= 255 {
-                    self.error(self.peek()?, "Can't have more than 255 parameters.");
+                    self.error_at_next("Can't have more than 255 parameters.");
                 }
                 parameters.push(self.consume(&TokenType::Identifier, "Expect parameter name.")?);
                 if !self.matching(&[TokenType::Comma]) {
@@ -196,43 +365,107 @@ impl Parser {
             }
         }
 
-        self.consume(&TokenType::RightParen, "Expect ')' after parameters.");
-        self.consume(&TokenType::LeftBrace, &("Expect '{' before ".to_string() + &kind + " body."));
-        let body: Vec<Stmt> = self.block();
-        Ok(Stmt::Function(Box::new(Function { name, parameters, body })))
+        self.consume(&TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(
+            &TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        // A loop around the declaration doesn't make `break` valid inside it.
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        self.function_depth += 1;
+        let body = self.block();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        let body = body?;
+        Ok(Function {
+            name,
+            parameters,
+            body,
+            slot: Default::default(),
+            layout: Default::default(),
+        })
     }
 
-    fn block(&mut self) -> Vec<Stmt> {
+    fn block(&mut self) -> Result<Vec<Stmt>, Error> {
         let mut statements = Vec::new();
 
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration().unwrap());
+            statements.push(self.declaration()?);
         }
-        self.consume(&TokenType::RightBrace, "Expect '}' after block.");
-        statements
+        self.consume(&TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
     }
 
     fn assignment(&mut self) -> Result<Expr, Error> {
-        let expr: Expr = self.or()?;
-
-        if self.matching(&[TokenType::Equal]) {
-            let equals: Token = self.previous();
-            let val: Expr = self.assignment()?;
-
+        let expr: Expr = self.conditional()?;
+
+        if self.matching(&[
+            TokenType::Equal,
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+            TokenType::PercentEqual,
+        ]) {
+            let equals = self.previous().span();
+            let mut val: Expr = self.assignment()?;
+
+            if let Expr::Index(index) = expr {
+                return Ok(Expr::SetIndex(Box::new(SetIndex {
+                    object: index.object,
+                    bracket: index.bracket,
+                    index: index.index,
+                    operator: compound_operator(equals),
+                    value: val,
+                })));
+            }
             if let Expr::Variable(var) = expr {
-                let name: Token = var.name;
-                return Ok(Expr::Assign(Box::new(Assign { name, value: val })));
+                let name = var.name;
+                if let Some(operator) = compound_operator(equals) {
+                    val = Expr::Binary(Box::new(Binary {
+                        left: Expr::Variable(Box::new(Variable {
+                            name,
+                            slot: Default::default(),
+                        })),
+                        operator,
+                        right: val,
+                    }));
+                }
+                return Ok(Expr::Assign(Box::new(Assign {
+                    name,
+                    value: val,
+                    slot: Default::default(),
+                })));
             }
             return Err(self.error(equals, "Invalid Assignment target."));
         }
-        return Ok(expr);
+        Ok(expr)
+    }
+
+    fn conditional(&mut self) -> Result<Expr, Error> {
+        let condition = self.or()?;
+
+        if self.matching(&[TokenType::Question]) {
+            let then_branch = self.expression()?;
+            self.consume(
+                &TokenType::Colon,
+                "Expect ':' after then branch of conditional.",
+            )?;
+            let else_branch = self.conditional()?;
+            return Ok(Expr::Conditional(Box::new(Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            })));
+        }
+        Ok(condition)
     }
 
     fn or(&mut self) -> Result<Expr, Error> {
         let mut expr = self.and()?;
 
         while self.matching(&[TokenType::Or]) {
-            let operator = self.previous();
+            let operator = self.previous().span();
             let right = self.and()?;
             expr = Expr::Logical(Box::new(Logical {
                 left: expr,
@@ -247,7 +480,7 @@ impl Parser {
         let mut expr = self.equality()?;
 
         while self.matching(&[TokenType::And]) {
-            let operator = self.previous();
+            let operator = self.previous().span();
             let right = self.equality()?;
             expr = Expr::Logical(Box::new(Logical {
                 left: expr,
@@ -262,7 +495,7 @@ impl Parser {
         let mut expr: Expr = self.comparison()?;
 
         while self.matching(&[TokenType::BangEqual, TokenType::EqualEqual]) {
-            let op: Token = self.previous();
+            let op = self.previous().span();
             let r: Expr = self.comparison()?;
             expr = Expr::Binary(Box::new(Binary {
                 left: expr,
@@ -270,7 +503,7 @@ impl Parser {
                 right: r,
             }));
         }
-        return Ok(expr);
+        Ok(expr)
     }
 
     fn comparison(&mut self) -> Result<Expr, Error> {
@@ -282,7 +515,7 @@ impl Parser {
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
-            let op: Token = self.previous();
+            let op = self.previous().span();
             let r: Expr = self.term()?;
             expr = Expr::Binary(Box::new(Binary {
                 left: expr,
@@ -290,14 +523,14 @@ impl Parser {
                 right: r,
             }));
         }
-        return Ok(expr);
+        Ok(expr)
     }
 
     fn term(&mut self) -> Result<Expr, Error> {
         let mut expr: Expr = self.factor()?;
 
         while self.matching(&[TokenType::Minus, TokenType::Plus]) {
-            let op: Token = self.previous();
+            let op = self.previous().span();
             let r: Expr = self.factor()?;
 
             expr = Expr::Binary(Box::new(Binary {
@@ -306,32 +539,28 @@ impl Parser {
                 right: r,
             }));
         }
-        return Ok(expr);
+        Ok(expr)
     }
 
     fn factor(&mut self) -> Result<Expr, Error> {
-        let expr: Expr = self.unary()?;
+        let mut expr: Expr = self.unary()?;
 
-        while self.matching(&[TokenType::Slash, TokenType::Star]) {
-            let op: Token = self.previous();
-            match self.unary() {
-                Ok(r) => {
-                    return Ok(Expr::Binary(Box::new(Binary {
-                        left: expr,
-                        operator: op,
-                        right: r,
-                    })))
-                }
-                Err(e) => return Err(e),
-            };
-        }
+        while self.matching(&[TokenType::Slash, TokenType::Star, TokenType::Percent]) {
+            let op = self.previous().span();
+            let r: Expr = self.unary()?;
 
-        return Ok(expr);
+            expr = Expr::Binary(Box::new(Binary {
+                left: expr,
+                operator: op,
+                right: r,
+            }));
+        }
+        Ok(expr)
     }
 
     fn unary(&mut self) -> Result<Expr, Error> {
         if self.matching(&[TokenType::Bang, TokenType::Minus]) {
-            let op: Token = self.previous();
+            let op = self.previous().span();
             match self.unary() {
                 Ok(r) => {
                     return Ok(Expr::Unary(Box::new(Unary {
@@ -342,7 +571,7 @@ impl Parser {
                 Err(e) => return Err(e),
             };
         }
-        return self.call();
+        self.call()
     }
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, Error> {
@@ -350,7 +579,7 @@ impl Parser {
         if !self.check(&TokenType::RightParen) {
             loop {
                 if arguments.len() >= 255 {
-                    self.error(self.peek()?, "Can't have more than 255 arguments.");
+                    self.error_at_next("Can't have more than 255 arguments.");
                 }
 
                 arguments.push(self.expression()?);
@@ -374,6 +603,18 @@ impl Parser {
         loop {
             if self.matching(&[TokenType::LeftParen]) {
                 expr = self.finish_call(expr)?;
+            } else if self.matching(&[TokenType::Dot]) {
+                let name =
+                    self.consume(&TokenType::Identifier, "Expect property name after '.'.")?;
+                expr = Expr::Get(Box::new(Get { object: expr, name }));
+            } else if self.matching(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self.consume(&TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = Expr::Index(Box::new(Index {
+                    object: expr,
+                    bracket,
+                    index,
+                }));
             } else {
                 break;
             }
@@ -383,23 +624,27 @@ impl Parser {
 
     fn primary(&mut self) -> Result<Expr, Error> {
         if self.matching(&[TokenType::False]) {
-            return Ok(Expr::Literal(Literal::Str(String::from("false"))));
+            return Ok(Expr::Literal(Literal::Bool(false)));
         }
         if self.matching(&[TokenType::True]) {
-            return Ok(Expr::Literal(Literal::Str(String::from("true"))));
+            return Ok(Expr::Literal(Literal::Bool(true)));
         }
         if self.matching(&[TokenType::Nil]) {
-            return Ok(Expr::Literal(Literal::Str(String::from("null"))));
+            return Ok(Expr::Literal(Literal::Nil));
         }
         if self.matching(&[TokenType::Number, TokenType::String]) {
-            return Ok(Expr::Literal(self.previous().literal.unwrap()));
+            return Ok(Expr::Literal(self.previous().literal.clone().unwrap()));
         }
         if self.matching(&[TokenType::Identifier]) {
             return Ok(Expr::Variable(Box::new(Variable {
-                name: self.previous(),
+                name: self.previous().span(),
+                slot: Default::default(),
             })));
         }
 
+        if self.check(&TokenType::LeftParen) && self.is_arrow_function() {
+            return self.arrow_function();
+        }
         if self.matching(&[TokenType::LeftParen]) {
             match self.expression() {
                 Ok(expr) => {
@@ -409,10 +654,92 @@ impl Parser {
                 Err(e) => return Err(e),
             };
         }
-        match self.peek() {
-            Ok(token) => Err(self.error(token, "Expect expression.")),
-            Err(e) => Err(e),
+        if self.matching(&[TokenType::LeftBracket]) {
+            return self.list();
         }
+        if self.matching(&[TokenType::Fun]) {
+            self.consume(&TokenType::LeftParen, "Expect '(' after 'fun'.")?;
+            let function = self.function_body("function", None)?;
+            return Ok(Expr::Function(Rc::new(function)));
+        }
+        // Blocks are statements, so a brace in expression position is always
+        // a map literal.
+        if self.matching(&[TokenType::LeftBrace]) {
+            return self.map();
+        }
+        Err(self.error_at_next("Expect expression."))
+    }
+
+    /// Whether `(` starts an arrow function such as `(x) => x` rather than a grouping.
+    fn is_arrow_function(&mut self) -> bool {
+        let mut n = 1;
+        if self.check_nth(n, &TokenType::Identifier) {
+            n += 1;
+            while self.check_nth(n, &TokenType::Comma)
+                && self.check_nth(n + 1, &TokenType::Identifier)
+            {
+                n += 2;
+            }
+        }
+        self.check_nth(n, &TokenType::RightParen) && self.check_nth(n + 1, &TokenType::Arrow)
+    }
+
+    /// `(parameters) => expression` is shorthand for a function expression
+    /// returning `expression`.
+    fn arrow_function(&mut self) -> Result<Expr, Error> {
+        self.consume(&TokenType::LeftParen, "Expect '(' before parameters.")?;
+        let mut parameters = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                parameters.push(self.consume(&TokenType::Identifier, "Expect parameter name.")?);
+                if !self.matching(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(&TokenType::RightParen, "Expect ')' after parameters.")?;
+        let keyword = self.consume(&TokenType::Arrow, "Expect '=>' after parameters.")?;
+        let value = self.expression()?;
+        Ok(Expr::Function(Rc::new(Function {
+            name: None,
+            parameters,
+            body: vec![Stmt::Return(Return {
+                keyword,
+                value: Some(value),
+            })],
+            slot: Default::default(),
+            layout: Default::default(),
+        })))
+    }
+
+    /// Parses the `key: value` entries of a map literal. A trailing comma is
+    /// allowed.
+    fn map(&mut self) -> Result<Expr, Error> {
+        let mut entries = Vec::new();
+        while !self.check(&TokenType::RightBrace) {
+            let key = self.expression()?;
+            self.consume(&TokenType::Colon, "Expect ':' after map key.")?;
+            let value = self.expression()?;
+            entries.push((key, value));
+            if !self.matching(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        let brace = self.consume(&TokenType::RightBrace, "Expect '}' after map entries.")?;
+        Ok(Expr::Map(Box::new(Map { brace, entries })))
+    }
+
+    /// Parses the elements of a list literal. A trailing comma is allowed.
+    fn list(&mut self) -> Result<Expr, Error> {
+        let mut elements = Vec::new();
+        while !self.check(&TokenType::RightBracket) {
+            elements.push(self.expression()?);
+            if !self.matching(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        let bracket = self.consume(&TokenType::RightBracket, "Expect ']' after list elements.")?;
+        Ok(Expr::List(Box::new(List { bracket, elements })))
     }
 
     fn matching(&mut self, ttypes: &[TokenType]) -> bool {
@@ -422,20 +749,17 @@ impl Parser {
                 return true;
             }
         }
-        return false;
+        false
     }
 
-    fn consume(&mut self, ttype: &TokenType, message: &str) -> Result<Token, Error> {
+    fn consume(&mut self, ttype: &TokenType, message: &str) -> Result<Span, Error> {
         if self.check(ttype) {
             return Ok(self.advance());
         }
-        match self.peek() {
-            Ok(token) => Err(self.error(token, message)),
-            Err(e) => Err(e),
-        }
+        Err(self.error_at_next(message))
     }
 
-    fn check(&self, ttype: &TokenType) -> bool {
+    fn check(&mut self, ttype: &TokenType) -> bool {
         if self.is_at_end() {
             return false;
         };
@@ -445,36 +769,68 @@ impl Parser {
         }
     }
 
-    fn advance(&mut self) -> Token {
+    /// Looks `n` tokens past the current one without consuming anything.
+    fn check_nth(&mut self, n: usize, ttype: &TokenType) -> bool {
+        self.fill(n + 1);
+        match self.lookahead.get(n) {
+            Some(token) => &token.ttype == ttype,
+            None => false,
+        }
+    }
+
+    fn advance(&mut self) -> Span {
         if !self.is_at_end() {
-            self.current += 1
+            self.previous = self.lookahead.pop_front();
         };
-        self.previous()
+        self.previous().span()
     }
 
-    fn is_at_end(&self) -> bool {
-        if self.current >= self.tokens.len() {
-            true;
-        }
+    fn is_at_end(&mut self) -> bool {
         match self.peek() {
             Ok(token) => token.ttype == TokenType::Eof,
-            Err(e) => true,
+            Err(_) => true,
         }
     }
 
-    fn peek(&self) -> Result<Token, Error> {
-        match self.tokens.get(self.current) {
-            Some(token) => return Ok(token.clone()),
-            None => Err(Error::ParseError),
+    /// Buffers `n` tokens of lookahead. Scan errors are reported and skipped
+    /// so that parsing can carry on with the rest of the stream.
+    fn fill(&mut self, n: usize) {
+        while self.lookahead.len() < n {
+            match self.tokens.next() {
+                Some(Ok(token)) => self.lookahead.push_back(token),
+                Some(Err(e)) => {
+                    eprintln!("{}", e);
+                    self.scan_error = true;
+                }
+                None => break,
+            }
         }
     }
 
-    fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
+    fn peek(&mut self) -> Result<&Token, Error> {
+        self.fill(1);
+        self.lookahead.front().ok_or(Error::ParseError)
     }
 
-    fn error(&mut self, token: Token, message: &str) -> Error {
-        scanner::error(token, &message);
+    fn previous(&self) -> &Token {
+        self.previous
+            .as_ref()
+            .expect("previous() called before any token was consumed")
+    }
+
+    fn error(&mut self, token: Span, message: &str) -> Error {
+        scanner::error(&token, message);
+        self.synchronize();
+        Error::ParseError
+    }
+
+    /// Reports an error at the next token. Unlike its span, the token still
+    /// has the text of a number.
+    fn error_at_next(&mut self, message: &str) -> Error {
+        match self.peek() {
+            Ok(token) => scanner::token_error(token, message),
+            Err(err) => return err,
+        }
         self.synchronize();
         Error::ParseError
     }
@@ -486,12 +842,12 @@ impl Parser {
             if self.previous().ttype == TokenType::Semicolon {
                 return;
             }
-            let token = match self.peek() {
-                Ok(token) => token,
-                Err(e) => return,
+            let ttype = match self.peek() {
+                Ok(token) => token.ttype,
+                Err(_) => return,
             };
 
-            match token.ttype {
+            match ttype {
                 TokenType::Class => return,
                 TokenType::Fun => return,
                 TokenType::Var => return,
@@ -500,6 +856,9 @@ impl Parser {
                 TokenType::While => return,
                 TokenType::Print => return,
                 TokenType::Return => return,
+                TokenType::Import => return,
+                TokenType::Throw => return,
+                TokenType::Try => return,
                 _ => (),
             }
             self.advance();
@@ -511,6 +870,48 @@ impl Parser {
         while !self.is_at_end() {
             statements.push(self.declaration()?);
         }
+        if self.scan_error {
+            return Err(Error::ParseError);
+        }
         Ok(statements)
     }
 }
+
+/// Yields one top-level declaration at a time, so callers such as the REPL
+/// can execute statements as soon as they have been read.
+impl<I: Iterator<Item = Result<Token, scanner::Error>>> Iterator for Parser<I> {
+    type Item = Result<Stmt, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.scan_error = false;
+        if self.is_at_end() {
+            return match self.scan_error {
+                true => Some(Err(Error::ParseError)),
+                false => None,
+            };
+        }
+        let stmt = self.declaration();
+        if self.scan_error {
+            return Some(Err(Error::ParseError));
+        }
+        Some(stmt)
+    }
+}
+
+/// Maps a compound assignment token such as `+=` to the binary operator it
+/// applies, so `x += 1` can be desugared into `x = x + 1`.
+fn compound_operator(token: Span) -> Option<Span> {
+    let ttype = match token.ttype {
+        TokenType::PlusEqual => TokenType::Plus,
+        TokenType::MinusEqual => TokenType::Minus,
+        TokenType::StarEqual => TokenType::Star,
+        TokenType::SlashEqual => TokenType::Slash,
+        TokenType::PercentEqual => TokenType::Percent,
+        _ => return None,
+    };
+    Some(Span {
+        lexeme: Symbol::from_static(ttype.text()),
+        ttype,
+        ..token
+    })
+}
@@ -0,0 +1,520 @@
+//! Resolves variables for the tree-walking interpreter before it runs.
+//!
+//! Variables declared at the top level of a script or module are globals and
+//! are looked up by name. Every other variable gets a slot in the frame of
+//! the function that declares it, so reading it is an index and entering a
+//! block allocates nothing. The few variables that a nested function refers
+//! to live in cells instead, which the frame and the closure share.
+//!
+//! The resolver writes each variable's `Slot` into the syntax tree and each
+//! function's `Layout` into its declaration. The bytecode compiler reads the
+//! same slots to decide which of its locals are cells.
+
+use crate::{
+    compiler,
+    expr::Expr,
+    scanner::Span,
+    stmt::{Function, Stmt},
+    symbol::Symbol,
+};
+use std::{fmt, rc::Rc};
+
+#[derive(Debug)]
+pub enum Error {
+    /// A nested function refers to a variable that wasn't given a cell.
+    NotInCell(Span),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotInCell(name) => write!(
+                f,
+                "[line: {}] Error: Captured variable '{}' is not in a cell.",
+                name.line, name.lexeme
+            ),
+        }
+    }
+}
+
+impl Error {
+    pub fn line(&self) -> usize {
+        match self {
+            Error::NotInCell(name) => name.line,
+        }
+    }
+}
+
+/// Where a variable lives at runtime.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Slot {
+    /// Looked up by name in the current environment.
+    #[default]
+    Global,
+    /// An index into the running call's frame.
+    Local(usize),
+    /// A captured local, boxed in one of the running call's cells.
+    Cell(usize),
+    /// A variable of an enclosing function, captured by the running closure.
+    Upvalue(usize),
+}
+
+/// What a call needs to set up its frame.
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    /// The number of frame slots.
+    pub locals: usize,
+    pub cells: usize,
+    pub parameters: Vec<Slot>,
+    /// The variables a closure takes from the call that creates it, each a
+    /// `Slot::Cell` or `Slot::Upvalue` of that call.
+    pub captures: Vec<Slot>,
+}
+
+/// Resolves a script or module and returns the layout of its top-level
+/// frame.
+pub fn resolve(statements: &[Stmt]) -> Result<Layout, Error> {
+    let mut captures = Captures {
+        functions: vec![Vec::new()],
+        captured: Vec::new(),
+    };
+    captures.statements(statements);
+    let mut resolver = Resolver {
+        functions: vec![Scope::default()],
+        captured: captures.captured,
+        declarations: 0,
+    };
+    resolver.statements(statements)?;
+    Ok(resolver.functions.pop().unwrap().layout)
+}
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    slot: Slot,
+}
+
+/// The variables of one function while it is being resolved.
+#[derive(Default)]
+struct Scope {
+    locals: Vec<Local>,
+    depth: usize,
+    layout: Layout,
+}
+
+impl Scope {
+    fn resolve_local(&self, name: Symbol) -> Option<Slot> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|local| local.name == name)
+            .map(|local| local.slot)
+    }
+}
+
+struct Resolver {
+    /// The function being resolved and the ones it is nested in, innermost
+    /// last.
+    functions: Vec<Scope>,
+    /// Whether some nested function refers to each declaration, in the
+    /// order `Captures` met them.
+    captured: Vec<bool>,
+    /// The number of declarations resolved so far.
+    declarations: usize,
+}
+
+impl Resolver {
+    fn current(&mut self) -> &mut Scope {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) -> Result<(), Error> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        match stmt {
+            Stmt::Expression(stmt) => self.expression(&stmt.expression)?,
+            Stmt::Print(stmt) => self.expression(&stmt.expression)?,
+            Stmt::Var(stmt) => {
+                if let Some(initializer) = &stmt.initializer {
+                    self.expression(initializer)?;
+                }
+                stmt.slot.set(self.declare(stmt.name.lexeme));
+            }
+            Stmt::Block(block) => self.scoped(|this| this.statements(&block.statements))?,
+            Stmt::If(stmt) => {
+                self.expression(&stmt.condition)?;
+                self.statement(&stmt.then_branch)?;
+                if let Some(else_branch) = &stmt.else_branch {
+                    self.statement(else_branch)?;
+                }
+            }
+            Stmt::While(stmt) => {
+                self.expression(&stmt.condition)?;
+                self.statement(&stmt.body)?;
+                if let Some(increment) = &stmt.increment {
+                    self.expression(increment)?;
+                }
+            }
+            Stmt::ForIn(stmt) => {
+                self.expression(&stmt.iterable)?;
+                self.scoped(|this| {
+                    stmt.slot.set(this.declare(stmt.name.lexeme));
+                    this.statement(&stmt.body)
+                })?;
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => (),
+            Stmt::Function(function) => {
+                // Declared first so that the function can call itself.
+                let name = function.name.as_ref().unwrap();
+                function.slot.set(self.declare(name.lexeme));
+                self.function(function)?;
+            }
+            Stmt::Return(stmt) => {
+                if let Some(value) = &stmt.value {
+                    self.expression(value)?;
+                }
+            }
+            Stmt::Import(stmt) => {
+                stmt.slot.set(self.declare(compiler::import_binding(stmt)));
+            }
+            Stmt::Throw(stmt) => self.expression(&stmt.value)?,
+            Stmt::Try(stmt) => {
+                self.scoped(|this| this.statements(&stmt.body))?;
+                if let Some(catch) = &stmt.catch {
+                    self.scoped(|this| {
+                        catch.slot.set(this.declare(catch.name.lexeme));
+                        this.statements(&catch.body)
+                    })?;
+                }
+                if let Some(finally) = &stmt.finally {
+                    self.scoped(|this| this.statements(finally))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, function: &Rc<Function>) -> Result<(), Error> {
+        self.functions.push(Scope {
+            depth: 1,
+            ..Scope::default()
+        });
+        let parameters = function
+            .parameters
+            .iter()
+            .map(|parameter| self.declare(parameter.lexeme))
+            .collect();
+        self.current().layout.parameters = parameters;
+        self.statements(&function.body)?;
+        let scope = self.functions.pop().unwrap();
+        *function.layout.borrow_mut() = scope.layout;
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), Error> {
+        match expr {
+            Expr::Literal(_) => (),
+            Expr::Grouping(expr) => self.expression(&expr.expression)?,
+            Expr::Unary(expr) => self.expression(&expr.right)?,
+            Expr::Binary(expr) => {
+                self.expression(&expr.left)?;
+                self.expression(&expr.right)?;
+            }
+            Expr::Variable(expr) => expr.slot.set(self.resolve(&expr.name)?),
+            Expr::Assign(expr) => {
+                self.expression(&expr.value)?;
+                expr.slot.set(self.resolve(&expr.name)?);
+            }
+            Expr::Logical(expr) => {
+                self.expression(&expr.left)?;
+                self.expression(&expr.right)?;
+            }
+            Expr::Conditional(expr) => {
+                self.expression(&expr.condition)?;
+                self.expression(&expr.then_branch)?;
+                self.expression(&expr.else_branch)?;
+            }
+            Expr::Call(expr) => {
+                self.expression(&expr.callee)?;
+                for argument in &expr.arguments {
+                    self.expression(argument)?;
+                }
+            }
+            Expr::List(expr) => {
+                for element in &expr.elements {
+                    self.expression(element)?;
+                }
+            }
+            Expr::Map(expr) => {
+                for (key, value) in &expr.entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+            }
+            Expr::Index(expr) => {
+                self.expression(&expr.object)?;
+                self.expression(&expr.index)?;
+            }
+            Expr::SetIndex(expr) => {
+                self.expression(&expr.object)?;
+                self.expression(&expr.index)?;
+                self.expression(&expr.value)?;
+            }
+            Expr::Get(expr) => self.expression(&expr.object)?,
+            Expr::Function(function) => self.function(function)?,
+        }
+        Ok(())
+    }
+
+    fn scoped(&mut self, walk: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+        self.current().depth += 1;
+        let result = walk(self);
+        let scope = self.current();
+        scope.depth -= 1;
+        let depth = scope.depth;
+        while scope.locals.last().is_some_and(|local| local.depth > depth) {
+            scope.locals.pop();
+        }
+        result
+    }
+
+    /// Picks the slot of a new variable: a global at the top level,
+    /// otherwise the next free frame slot, or a cell if a closure captures
+    /// it.
+    fn declare(&mut self, name: Symbol) -> Slot {
+        let captured = self.captured[self.declarations];
+        self.declarations += 1;
+        let scope = self.current();
+        if scope.depth == 0 {
+            return Slot::Global;
+        }
+        let slot = if captured {
+            scope.layout.cells += 1;
+            Slot::Cell(scope.layout.cells - 1)
+        } else {
+            let index = scope
+                .locals
+                .iter()
+                .filter(|local| matches!(local.slot, Slot::Local(_)))
+                .count();
+            scope.layout.locals = scope.layout.locals.max(index + 1);
+            Slot::Local(index)
+        };
+        scope.locals.push(Local {
+            name,
+            depth: scope.depth,
+            slot,
+        });
+        slot
+    }
+
+    /// A local of the innermost function, a variable captured from an
+    /// enclosing one, or a global.
+    fn resolve(&mut self, name: &Span) -> Result<Slot, Error> {
+        let top = self.functions.len() - 1;
+        if let Some(slot) = self.functions[top].resolve_local(name.lexeme) {
+            return Ok(slot);
+        }
+        Ok(match self.resolve_upvalue(top, name)? {
+            Some(upvalue) => Slot::Upvalue(upvalue),
+            None => Slot::Global,
+        })
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &Span) -> Result<Option<usize>, Error> {
+        if function == 0 {
+            return Ok(None);
+        }
+        let enclosing = function - 1;
+        let capture = match self.functions[enclosing].resolve_local(name.lexeme) {
+            Some(Slot::Cell(cell)) => Slot::Cell(cell),
+            Some(_) => return Err(Error::NotInCell(*name)),
+            None => match self.resolve_upvalue(enclosing, name)? {
+                Some(upvalue) => Slot::Upvalue(upvalue),
+                None => return Ok(None),
+            },
+        };
+        let captures = &mut self.functions[function].layout.captures;
+        if let Some(index) = captures.iter().position(|c| *c == capture) {
+            return Ok(Some(index));
+        }
+        captures.push(capture);
+        Ok(Some(captures.len() - 1))
+    }
+}
+
+/// Finds the local variables that nested functions refer to, so that only
+/// those are boxed into cells. Walks the tree in the same order as
+/// `Resolver`, numbering the declarations as it meets them. Top-level
+/// declarations are globals and are never captured.
+struct Captures {
+    /// Scopes of each function being walked, innermost last, holding the
+    /// number of each declaration.
+    functions: Vec<Vec<Vec<(Symbol, usize)>>>,
+    captured: Vec<bool>,
+}
+
+impl Captures {
+    fn declare(&mut self, name: Symbol) {
+        let declaration = self.captured.len();
+        self.captured.push(false);
+        if let Some(scope) = self.functions.last_mut().unwrap().last_mut() {
+            scope.push((name, declaration));
+        }
+    }
+
+    fn resolve(&mut self, name: Symbol) {
+        let innermost = self.functions.len() - 1;
+        for (i, scopes) in self.functions.iter().enumerate().rev() {
+            let found = scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.iter().rev().find(|(local, _)| *local == name));
+            if let Some(&(_, declaration)) = found {
+                if i != innermost {
+                    self.captured[declaration] = true;
+                }
+                return;
+            }
+        }
+    }
+
+    fn scoped(&mut self, walk: impl FnOnce(&mut Self)) {
+        self.functions.last_mut().unwrap().push(Vec::new());
+        walk(self);
+        self.functions.last_mut().unwrap().pop();
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(stmt) => self.expression(&stmt.expression),
+            Stmt::Print(stmt) => self.expression(&stmt.expression),
+            Stmt::Var(stmt) => {
+                if let Some(initializer) = &stmt.initializer {
+                    self.expression(initializer);
+                }
+                self.declare(stmt.name.lexeme);
+            }
+            Stmt::Block(block) => self.scoped(|this| this.statements(&block.statements)),
+            Stmt::If(stmt) => {
+                self.expression(&stmt.condition);
+                self.statement(&stmt.then_branch);
+                if let Some(else_branch) = &stmt.else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::While(stmt) => {
+                self.expression(&stmt.condition);
+                self.statement(&stmt.body);
+                if let Some(increment) = &stmt.increment {
+                    self.expression(increment);
+                }
+            }
+            Stmt::ForIn(stmt) => {
+                self.expression(&stmt.iterable);
+                self.scoped(|this| {
+                    this.declare(stmt.name.lexeme);
+                    this.statement(&stmt.body);
+                });
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => (),
+            Stmt::Function(function) => {
+                self.declare(function.name.as_ref().unwrap().lexeme);
+                self.function(function);
+            }
+            Stmt::Return(stmt) => {
+                if let Some(value) = &stmt.value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Import(stmt) => self.declare(compiler::import_binding(stmt)),
+            Stmt::Throw(stmt) => self.expression(&stmt.value),
+            Stmt::Try(stmt) => {
+                self.scoped(|this| this.statements(&stmt.body));
+                if let Some(catch) = &stmt.catch {
+                    self.scoped(|this| {
+                        this.declare(catch.name.lexeme);
+                        this.statements(&catch.body);
+                    });
+                }
+                if let Some(finally) = &stmt.finally {
+                    self.scoped(|this| this.statements(finally));
+                }
+            }
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.functions.push(vec![Vec::new()]);
+        for parameter in &function.parameters {
+            self.declare(parameter.lexeme);
+        }
+        self.statements(&function.body);
+        self.functions.pop();
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(_) => (),
+            Expr::Grouping(expr) => self.expression(&expr.expression),
+            Expr::Unary(expr) => self.expression(&expr.right),
+            Expr::Binary(expr) => {
+                self.expression(&expr.left);
+                self.expression(&expr.right);
+            }
+            Expr::Variable(expr) => self.resolve(expr.name.lexeme),
+            Expr::Assign(expr) => {
+                self.expression(&expr.value);
+                self.resolve(expr.name.lexeme);
+            }
+            Expr::Logical(expr) => {
+                self.expression(&expr.left);
+                self.expression(&expr.right);
+            }
+            Expr::Conditional(expr) => {
+                self.expression(&expr.condition);
+                self.expression(&expr.then_branch);
+                self.expression(&expr.else_branch);
+            }
+            Expr::Call(expr) => {
+                self.expression(&expr.callee);
+                for argument in &expr.arguments {
+                    self.expression(argument);
+                }
+            }
+            Expr::List(expr) => {
+                for element in &expr.elements {
+                    self.expression(element);
+                }
+            }
+            Expr::Map(expr) => {
+                for (key, value) in &expr.entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            Expr::Index(expr) => {
+                self.expression(&expr.object);
+                self.expression(&expr.index);
+            }
+            Expr::SetIndex(expr) => {
+                self.expression(&expr.object);
+                self.expression(&expr.index);
+                self.expression(&expr.value);
+            }
+            Expr::Get(expr) => self.expression(&expr.object),
+            Expr::Function(function) => self.function(function),
+        }
+    }
+}
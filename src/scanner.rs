@@ -1,104 +1,287 @@
+use crate::symbol::Symbol;
+use crate::value::Str;
+use std::collections::VecDeque;
 use std::fmt;
+use std::io::{self, BufReader, Bytes, Read};
 use std::str;
 
 #[rustfmt::skip]
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum TokenType {
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Minus, Plus, Semicolon, Slash, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    Comma, Dot, Minus, Plus, Semicolon, Slash, Star, Percent,
+    Question, Colon,
     // One or two character tokens.
     Bang, BangEqual,
     Equal, EqualEqual,
     Greater, GreaterEqual,
     Less, LessEqual,
+    PlusEqual, MinusEqual, StarEqual, SlashEqual, PercentEqual,
+    Arrow,
     // Literals.
     Identifier, String, Number,
     // Keywords.
-    And, Class, Else, False, Fun, For, If, Nil, Or,
-    Print, Return, Super, This, True, Var, While,
+    And, As, Break, Catch, Class, Continue, Else, False, Finally, Fun, For, If, Import, In,
+    Nil, Or, Print, Return, Super, This, Throw, True, Try, Var, While,
 
     Eof,
 }
 
 #[derive(Debug, Clone)]
 pub enum Literal {
-    Identifier(String),
-    Str(String),
+    Identifier(Symbol),
+    Str(Str),
     Number(f64),
+    Bool(bool),
+    Nil,
+}
+
+/// Source text between tokens. Only recorded when the scanner runs with
+/// `with_trivia`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    Whitespace(String),
+    Newline,
+    Comment(String),
+    /// Text the scanner could not turn into a token.
+    Skipped(String),
+}
+
+/// The text of a token. Identifiers and string literals are interned, and
+/// keywords and punctuation share the static symbol of their type. Numbers
+/// keep their own text rather than filling the interner.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lexeme {
+    Symbol(Symbol),
+    Number(Box<str>),
 }
 
 #[derive(Debug, Clone)]
 pub struct Token {
-    pub lexeme: String,
+    pub lexeme: Lexeme,
     pub line: usize,
     pub literal: Option<Literal>,
     pub ttype: TokenType,
+    /// Byte offsets of the lexeme in the source.
+    pub start: u32,
+    pub end: u32,
+    /// Boxed so that tokens scanned without trivia stay small.
+    pub trivia: Option<Box<TokenTrivia>>,
 }
 
-pub struct Scanner {
-    pub source: Vec<u8>,
-    pub list: Vec<Token>,
-    pub current: usize,
-    pub start: usize,
+/// What the syntax tree and runtime errors keep of a token. It is `Copy`, so
+/// the parser hands spans out without cloning tokens and the AST stores them
+/// inline. The lexeme of a number is left empty; the syntax tree holds its
+/// value instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub ttype: TokenType,
+    pub lexeme: Symbol,
     pub line: usize,
+    /// Byte offsets of the lexeme in the source. Both are zero for spans
+    /// that the VM makes up for its error messages.
+    pub start: u32,
+    pub end: u32,
 }
 
-impl fmt::Display for TokenType {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+#[derive(Debug, Clone, Default)]
+pub struct TokenTrivia {
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+}
+
+pub struct Scanner<R: Read> {
+    source: Bytes<BufReader<R>>,
+    lookahead: VecDeque<u8>,
+    lexeme: Vec<u8>,
+    line: usize,
+    /// Bytes consumed so far.
+    offset: usize,
+    io_error: Option<io::Error>,
+    finished: bool,
+    keep_trivia: bool,
+    pending_trivia: Vec<Trivia>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    UnterminatedString(usize),
+    UnexpectedCharacter(usize, char),
+    Io(io::Error),
+}
+
+impl TokenType {
+    /// The text of every keyword or punctuation token of this type, and
+    /// nothing for identifiers, literals and the end of input.
+    pub fn text(self) -> &'static str {
         match self {
-            TokenType::LeftParen => write!(f, "{}", self),
-            TokenType::RightParen => write!(f, "{}", self),
-            TokenType::LeftBrace => write!(f, "{}", self),
-            TokenType::RightBrace => write!(f, "{}", self),
-            TokenType::Comma => write!(f, "{}", self),
-            TokenType::Dot => write!(f, "{}", self),
-            TokenType::Minus => write!(f, "{}", self),
-            TokenType::Plus => write!(f, "{}", self),
-            TokenType::Semicolon => write!(f, "{}", self),
-            TokenType::Slash => write!(f, "{}", self),
-            TokenType::Star => write!(f, "{}", self),
-            TokenType::Bang => write!(f, "{}", self),
-            TokenType::BangEqual => write!(f, "{}", self),
-            TokenType::Equal => write!(f, "{}", self),
-            TokenType::EqualEqual => write!(f, "{}", self),
-            TokenType::Greater => write!(f, "{}", self),
-            TokenType::GreaterEqual => write!(f, "{}", self),
-            TokenType::Less => write!(f, "{}", self),
-            TokenType::LessEqual => write!(f, "{}", self),
-            TokenType::Identifier => write!(f, "{}", self),
-            TokenType::String => write!(f, "{}", self),
-            TokenType::Number => write!(f, "{}", self),
-            TokenType::And => write!(f, "{}", self),
-            TokenType::Class => write!(f, "{}", self),
-            TokenType::Else => write!(f, "{}", self),
-            TokenType::False => write!(f, "{}", self),
-            TokenType::Fun => write!(f, "{}", self),
-            TokenType::For => write!(f, "{}", self),
-            TokenType::If => write!(f, "{}", self),
-            TokenType::Nil => write!(f, "{}", self),
-            TokenType::Or => write!(f, "{}", self),
-            TokenType::Print => write!(f, "{}", self),
-            TokenType::Return => write!(f, "{}", self),
-            TokenType::Super => write!(f, "{}", self),
-            TokenType::This => write!(f, "{}", self),
-            TokenType::True => write!(f, "{}", self),
-            TokenType::Var => write!(f, "{}", self),
-            TokenType::While => write!(f, "{}", self),
-            TokenType::Eof => write!(f, "{}", self),
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::LeftBrace => "{",
+            TokenType::RightBrace => "}",
+            TokenType::LeftBracket => "[",
+            TokenType::RightBracket => "]",
+            TokenType::Comma => ",",
+            TokenType::Dot => ".",
+            TokenType::Minus => "-",
+            TokenType::Plus => "+",
+            TokenType::Semicolon => ";",
+            TokenType::Slash => "/",
+            TokenType::Star => "*",
+            TokenType::Percent => "%",
+            TokenType::Question => "?",
+            TokenType::Colon => ":",
+            TokenType::Bang => "!",
+            TokenType::BangEqual => "!=",
+            TokenType::Equal => "=",
+            TokenType::EqualEqual => "==",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::PlusEqual => "+=",
+            TokenType::MinusEqual => "-=",
+            TokenType::StarEqual => "*=",
+            TokenType::SlashEqual => "/=",
+            TokenType::PercentEqual => "%=",
+            TokenType::Arrow => "=>",
+            TokenType::And => "and",
+            TokenType::As => "as",
+            TokenType::Break => "break",
+            TokenType::Catch => "catch",
+            TokenType::Class => "class",
+            TokenType::Continue => "continue",
+            TokenType::Else => "else",
+            TokenType::False => "false",
+            TokenType::Finally => "finally",
+            TokenType::Fun => "fun",
+            TokenType::For => "for",
+            TokenType::If => "if",
+            TokenType::Import => "import",
+            TokenType::In => "in",
+            TokenType::Nil => "nil",
+            TokenType::Or => "or",
+            TokenType::Print => "print",
+            TokenType::Return => "return",
+            TokenType::Super => "super",
+            TokenType::This => "this",
+            TokenType::Throw => "throw",
+            TokenType::True => "true",
+            TokenType::Try => "try",
+            TokenType::Var => "var",
+            TokenType::While => "while",
+            TokenType::Identifier | TokenType::String | TokenType::Number | TokenType::Eof => "",
         }
     }
 }
 
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Literal::Identifier(id) => write!(f, "{}", id),
             Literal::Str(str) => write!(f, "{}", str),
             Literal::Number(num) => write!(f, "{}", num),
+            Literal::Bool(bool) => write!(f, "{}", bool),
+            Literal::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+impl Lexeme {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Lexeme::Symbol(symbol) => symbol.as_str(),
+            Lexeme::Number(text) => text,
+        }
+    }
+}
+
+impl fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for Trivia {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trivia::Whitespace(text) => write!(f, "{}", text),
+            Trivia::Newline => writeln!(f),
+            Trivia::Comment(text) => write!(f, "{}", text),
+            Trivia::Skipped(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl Token {
+    /// The token exactly as it appeared in the source, trivia included.
+    pub fn full_text(&self) -> String {
+        let mut text = String::new();
+        let (leading, trailing) = match &self.trivia {
+            Some(trivia) => (&trivia.leading[..], &trivia.trailing[..]),
+            None => (&[][..], &[][..]),
+        };
+        for trivia in leading {
+            text.push_str(&trivia.to_string());
+        }
+        text.push_str(self.lexeme.as_str());
+        for trivia in trailing {
+            text.push_str(&trivia.to_string());
+        }
+        text
+    }
+
+    pub fn span(&self) -> Span {
+        let lexeme = match self.lexeme {
+            Lexeme::Symbol(symbol) => symbol,
+            Lexeme::Number(_) => Symbol::EMPTY,
+        };
+        Span {
+            ttype: self.ttype,
+            lexeme,
+            line: self.line,
+            start: self.start,
+            end: self.end,
         }
     }
 }
 
+impl Span {
+    /// A span that matches no source text, for errors raised by code that
+    /// has no tokens to point at.
+    pub fn synthetic(ttype: TokenType, lexeme: &'static str, line: usize) -> Span {
+        Span {
+            ttype,
+            lexeme: Symbol::from_static(lexeme),
+            line,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// The literal the scanner attached to the token: the value of a
+    /// string, the text of a word and nothing for numbers and punctuation.
+    pub fn literal(&self) -> Literal {
+        let text = self.lexeme.as_str();
+        match self.ttype {
+            TokenType::String => Literal::Str(Symbol::intern(&text[1..text.len() - 1]).to_str()),
+            _ if text.starts_with(is_alpha) => Literal::Identifier(self.lexeme),
+            _ => Literal::Str(Symbol::EMPTY.to_str()),
+        }
+    }
+}
+
+/// Rebuilds the original source from a token list scanned `with_trivia`.
+pub fn to_source(tokens: &[Token]) -> String {
+    tokens.iter().map(Token::full_text).collect()
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -107,54 +290,123 @@ impl fmt::Display for Token {
             self.line,
             self.ttype,
             self.lexeme,
-            self.literal.as_ref().unwrap().to_string()
+            self.literal
+                .as_ref()
+                .map_or(String::new(), Literal::to_string)
         )
     }
 }
 
-impl Scanner {
-    pub fn scan_tokens(&mut self) {
-        while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token();
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Line: {}, Token type: {}, Lexeme: {} Literal: {}",
+            self.line,
+            self.ttype,
+            self.lexeme,
+            self.literal()
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnterminatedString(line) => {
+                write!(f, "[line: {}] Error:  : Unterminated string.", line)
+            }
+            Error::UnexpectedCharacter(line, c) => {
+                write!(
+                    f,
+                    "[line: {}] Error:  : Unexpected character '{}'.",
+                    line, c
+                )
+            }
+            Error::Io(e) => write!(f, "Error: could not read source: {}", e),
         }
     }
+}
 
-    fn is_at_end(&self) -> bool {
-        return self.current >= self.source.len();
+impl<R: Read> Scanner<R> {
+    pub fn new(source: R) -> Self {
+        Scanner {
+            source: BufReader::new(source).bytes(),
+            lookahead: VecDeque::new(),
+            lexeme: Vec::new(),
+            line: 1,
+            offset: 0,
+            io_error: None,
+            finished: false,
+            keep_trivia: false,
+            pending_trivia: Vec::new(),
+        }
+    }
+
+    /// Keeps whitespace, newlines and comments on the tokens instead of
+    /// discarding them, so that the token stream reproduces the source
+    /// byte-for-byte. A token owns the trivia before it and the trivia after
+    /// it up to the end of its line; the `Eof` token owns whatever is left.
+    pub fn with_trivia(mut self) -> Self {
+        self.keep_trivia = true;
+        self
+    }
+
+    /// Drains the scanner into a token list, stopping at the first error.
+    pub fn scan_tokens(self) -> Result<Vec<Token>, Error> {
+        self.collect()
+    }
+
+    /// Buffers up to `n` bytes of lookahead, stopping early at end of input.
+    fn fill(&mut self, n: usize) {
+        while self.lookahead.len() < n && self.io_error.is_none() {
+            match self.source.next() {
+                Some(Ok(byte)) => self.lookahead.push_back(byte),
+                Some(Err(e)) => self.io_error = Some(e),
+                None => break,
+            }
+        }
+    }
+
+    fn is_at_end(&mut self) -> bool {
+        self.fill(1);
+        self.lookahead.is_empty()
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        char::from(self.source[self.current - 1])
+        self.fill(1);
+        let byte = match self.lookahead.pop_front() {
+            Some(byte) => {
+                self.offset += 1;
+                byte
+            }
+            None => b'\0',
+        };
+        self.lexeme.push(byte);
+        char::from(byte)
     }
 
     fn match_token(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         };
-        if char::from(self.source[self.current]) != expected {
-            return false;
-        };
-        self.current += 1;
+        self.advance();
         true
     }
 
     fn peek(&mut self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        };
-        char::from(self.source[self.current])
+        self.fill(1);
+        self.lookahead
+            .front()
+            .map_or('\0', |&byte| char::from(byte))
     }
 
-    fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-        return char::from(self.source[self.current + 1]);
+    fn peek_next(&mut self) -> char {
+        self.fill(2);
+        self.lookahead.get(1).map_or('\0', |&byte| char::from(byte))
     }
 
-    fn string(&mut self) {
+    fn string(&mut self) -> Result<Token, Error> {
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
@@ -162,17 +414,21 @@ impl Scanner {
             self.advance();
         }
         if self.is_at_end() {
-            report(self.line, "", "Unterminated string.");
-            return;
+            return Err(Error::UnterminatedString(self.line));
         }
         self.advance();
 
-        let text = &self.source[self.start + 1..self.current - 1];
-        let value = str::from_utf8(text).unwrap().to_string();
-        self.make_token(TokenType::String, Literal::Str(value));
+        let text = &self.lexeme[1..self.lexeme.len() - 1];
+        let value = Symbol::intern(&String::from_utf8_lossy(text)).to_str();
+        let lexeme = Symbol::intern(&String::from_utf8_lossy(&self.lexeme));
+        Ok(self.make_token(
+            TokenType::String,
+            Lexeme::Symbol(lexeme),
+            Some(Literal::Str(value)),
+        ))
     }
 
-    fn number(&mut self) {
+    fn number(&mut self) -> Token {
         while is_digit(self.peek()) {
             self.advance();
         }
@@ -185,125 +441,275 @@ impl Scanner {
             }
         }
 
-        let text = &self.source[self.start..self.current];
-        let value = Literal::Number(
-            str::from_utf8(text)
-                .unwrap()
-                .to_string()
-                .parse::<f64>()
-                .unwrap(),
-        );
-        self.make_token(TokenType::Number, value)
+        let text = str::from_utf8(&self.lexeme).unwrap();
+        let value = Literal::Number(text.parse::<f64>().unwrap());
+        let lexeme = Lexeme::Number(text.into());
+        self.make_token(TokenType::Number, lexeme, Some(value))
     }
 
-    fn identifier(&mut self) {
+    fn identifier(&mut self) -> Token {
         while is_alphanumeric(self.peek()) {
             self.advance();
         }
-        let text = &self.source[self.start..self.current];
-        let text_str = str::from_utf8(text).unwrap().to_string();
-        self.keyword_or_identifier(&text_str);
-    }
-
-    fn keyword_or_identifier(&mut self, s: &str) {
-        match s {
-            "and" => self.add_token(TokenType::And, Some(s)),
-            "class" => self.add_token(TokenType::Class, Some(s)),
-            "else" => self.add_token(TokenType::Else, Some(s)),
-            "false" => self.add_token(TokenType::False, Some(s)),
-            "for" => self.add_token(TokenType::For, Some(s)),
-            "fun" => self.add_token(TokenType::Fun, Some(s)),
-            "if" => self.add_token(TokenType::If, Some(s)),
-            "nil" => self.add_token(TokenType::Nil, Some(s)),
-            "or" => self.add_token(TokenType::Or, Some(s)),
-            "print" => self.add_token(TokenType::Print, Some(s)),
-            "return" => self.add_token(TokenType::Return, Some(s)),
-            "super" => self.add_token(TokenType::Super, Some(s)),
-            "this" => self.add_token(TokenType::This, Some(s)),
-            "true" => self.add_token(TokenType::True, Some(s)),
-            "var" => self.add_token(TokenType::Var, Some(s)),
-            "while" => self.add_token(TokenType::While, Some(s)),
-            _ => {
-                self.add_token(TokenType::Identifier, Some(s));
-            }
-        }
+        let ttype = match str::from_utf8(&self.lexeme).unwrap() {
+            "and" => TokenType::And,
+            "as" => TokenType::As,
+            "break" => TokenType::Break,
+            "catch" => TokenType::Catch,
+            "class" => TokenType::Class,
+            "continue" => TokenType::Continue,
+            "else" => TokenType::Else,
+            "false" => TokenType::False,
+            "finally" => TokenType::Finally,
+            "for" => TokenType::For,
+            "fun" => TokenType::Fun,
+            "if" => TokenType::If,
+            "import" => TokenType::Import,
+            "in" => TokenType::In,
+            "nil" => TokenType::Nil,
+            "or" => TokenType::Or,
+            "print" => TokenType::Print,
+            "return" => TokenType::Return,
+            "super" => TokenType::Super,
+            "this" => TokenType::This,
+            "throw" => TokenType::Throw,
+            "true" => TokenType::True,
+            "try" => TokenType::Try,
+            "var" => TokenType::Var,
+            "while" => TokenType::While,
+            _ => TokenType::Identifier,
+        };
+        let text = match ttype {
+            TokenType::Identifier => Symbol::intern(str::from_utf8(&self.lexeme).unwrap()),
+            _ => Symbol::from_static(ttype.text()),
+        };
+        self.make_token(ttype, Lexeme::Symbol(text), Some(Literal::Identifier(text)))
     }
 
-    fn scan_token(&mut self) {
+    /// Scans a single lexeme. Whitespace and comments produce no token.
+    fn scan_token(&mut self) -> Option<Result<Token, Error>> {
         let c: char = self.advance();
-        match c {
-            '(' => self.add_token(TokenType::LeftParen, None),
-            ')' => self.add_token(TokenType::RightParen, None),
-            '{' => self.add_token(TokenType::LeftBrace, None),
-            '}' => self.add_token(TokenType::RightBrace, None),
-            ',' => self.add_token(TokenType::Comma, None),
-            '.' => self.add_token(TokenType::Dot, None),
-            '-' => self.add_token(TokenType::Minus, None),
-            '+' => self.add_token(TokenType::Plus, None),
-            ';' => self.add_token(TokenType::Semicolon, None),
-            '*' => self.add_token(TokenType::Star, None),
-            '!' => match self.match_token('=') {
-                true => self.add_token(TokenType::BangEqual, None),
-                false => self.add_token(TokenType::Bang, None),
+        let token = match c {
+            '(' => self.add_token(TokenType::LeftParen),
+            ')' => self.add_token(TokenType::RightParen),
+            '{' => self.add_token(TokenType::LeftBrace),
+            '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            ',' => self.add_token(TokenType::Comma),
+            '.' => self.add_token(TokenType::Dot),
+            '-' => match self.match_token('=') {
+                true => self.add_token(TokenType::MinusEqual),
+                false => self.add_token(TokenType::Minus),
+            },
+            '+' => match self.match_token('=') {
+                true => self.add_token(TokenType::PlusEqual),
+                false => self.add_token(TokenType::Plus),
             },
-            '=' => match self.match_token('=') {
-                true => self.add_token(TokenType::EqualEqual, None),
-                false => self.add_token(TokenType::Equal, None),
+            ';' => self.add_token(TokenType::Semicolon),
+            '?' => self.add_token(TokenType::Question),
+            ':' => self.add_token(TokenType::Colon),
+            '*' => match self.match_token('=') {
+                true => self.add_token(TokenType::StarEqual),
+                false => self.add_token(TokenType::Star),
             },
+            '%' => match self.match_token('=') {
+                true => self.add_token(TokenType::PercentEqual),
+                false => self.add_token(TokenType::Percent),
+            },
+            '!' => match self.match_token('=') {
+                true => self.add_token(TokenType::BangEqual),
+                false => self.add_token(TokenType::Bang),
+            },
+            '=' => {
+                if self.match_token('=') {
+                    self.add_token(TokenType::EqualEqual)
+                } else if self.match_token('>') {
+                    self.add_token(TokenType::Arrow)
+                } else {
+                    self.add_token(TokenType::Equal)
+                }
+            }
             '<' => match self.match_token('=') {
-                true => self.add_token(TokenType::LessEqual, None),
-                false => self.add_token(TokenType::Less, None),
+                true => self.add_token(TokenType::LessEqual),
+                false => self.add_token(TokenType::Less),
             },
             '>' => match self.match_token('=') {
-                true => self.add_token(TokenType::GreaterEqual, None),
-                false => self.add_token(TokenType::Greater, None),
+                true => self.add_token(TokenType::GreaterEqual),
+                false => self.add_token(TokenType::Greater),
             },
-            '/' => match self.match_token('/') {
-                true => {
+            '/' => {
+                if self.match_token('/') {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    return None;
                 }
-                false => self.add_token(TokenType::Slash, None),
-            },
-            ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
-            '"' => self.string(),
+                match self.match_token('=') {
+                    true => self.add_token(TokenType::SlashEqual),
+                    false => self.add_token(TokenType::Slash),
+                }
+            }
+            ' ' | '\r' | '\t' => return None,
+            '\n' => {
+                self.line += 1;
+                return None;
+            }
+            '"' => return Some(self.string()),
             c => {
                 if is_digit(c) {
-                    self.number();
+                    self.number()
                 } else if is_alpha(c) {
-                    self.identifier();
+                    self.identifier()
                 } else {
-                    report(self.line, "", "Unexpected character.");
+                    return Some(Err(Error::UnexpectedCharacter(
+                        self.line,
+                        self.unexpected_char(),
+                    )));
                 }
             }
         };
+        Some(Ok(token))
     }
 
-    fn make_token(&mut self, ttype: TokenType, literal: Literal) {
-        let text = &self.source[self.start..self.current];
-        let t = Token {
-            lexeme: str::from_utf8(text).unwrap().to_string(),
+    /// Consumes a run of trivia. Trailing trivia stops after the first
+    /// newline so the next line's indentation leads the next token.
+    fn trivia(&mut self, trailing: bool) -> Vec<Trivia> {
+        let mut trivia = Vec::new();
+        loop {
+            self.lexeme.clear();
+            match self.peek() {
+                ' ' | '\r' | '\t' => {
+                    while matches!(self.peek(), ' ' | '\r' | '\t') {
+                        self.advance();
+                    }
+                    trivia.push(Trivia::Whitespace(self.lexeme_text()));
+                }
+                '\n' => {
+                    self.advance();
+                    self.line += 1;
+                    trivia.push(Trivia::Newline);
+                    if trailing {
+                        break;
+                    }
+                }
+                '/' if self.peek_next() == '/' => {
+                    while self.peek() != '\n' && !self.is_at_end() {
+                        self.advance();
+                    }
+                    trivia.push(Trivia::Comment(self.lexeme_text()));
+                }
+                _ => break,
+            }
+        }
+        self.lexeme.clear();
+        trivia
+    }
+
+    /// Consumes the rest of a multi-byte UTF-8 sequence so that a stray
+    /// non-ASCII character is reported (and skipped) as a single character.
+    fn unexpected_char(&mut self) -> char {
+        while (0x80..0xc0).contains(&(self.peek() as u32)) {
+            self.advance();
+        }
+        self.lexeme_text().chars().next().unwrap_or('\0')
+    }
+
+    fn lexeme_text(&self) -> String {
+        String::from_utf8_lossy(&self.lexeme).into_owned()
+    }
+
+    fn make_token(&mut self, ttype: TokenType, lexeme: Lexeme, literal: Option<Literal>) -> Token {
+        Token {
+            lexeme,
             line: self.line,
-            literal: Some(literal),
+            literal,
             ttype,
-        };
-        println!("Token {:?}", t);
-        return self.list.push(t);
+            start: (self.offset - self.lexeme.len()) as u32,
+            end: self.offset as u32,
+            trivia: None,
+        }
+    }
+
+    /// A keyword, punctuation or the end of input, whose text is fixed.
+    fn add_token(&mut self, ttype: TokenType) -> Token {
+        let lexeme = Lexeme::Symbol(Symbol::from_static(ttype.text()));
+        self.make_token(ttype, lexeme, None)
     }
+}
+
+/// Tokens are produced lazily, one lexeme at a time, so only the current
+/// lexeme and a couple of bytes of lookahead are ever held in memory. The
+/// stream always ends with a single `Eof` token.
+impl<R: Read> Iterator for Scanner<R> {
+    type Item = Result<Token, Error>;
 
-    fn add_token(&mut self, ttype: TokenType, s: Option<&str>) {
-        let l = Literal::Str(String::from(s.unwrap_or("")));
-        self.make_token(ttype, l);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(e) = self.io_error.take() {
+                self.finished = true;
+                return Some(Err(Error::Io(e)));
+            }
+            if self.keep_trivia {
+                let leading = self.trivia(false);
+                self.pending_trivia.extend(leading);
+            }
+            if self.is_at_end() {
+                break;
+            }
+            self.lexeme.clear();
+            match self.scan_token() {
+                Some(Ok(mut token)) => {
+                    if self.keep_trivia {
+                        token.trivia = Some(Box::new(TokenTrivia {
+                            leading: std::mem::take(&mut self.pending_trivia),
+                            trailing: self.trivia(true),
+                        }));
+                    }
+                    return Some(Ok(token));
+                }
+                Some(Err(e)) => {
+                    if self.keep_trivia {
+                        let skipped = Trivia::Skipped(self.lexeme_text());
+                        self.pending_trivia.push(skipped);
+                    }
+                    return Some(Err(e));
+                }
+                None => (),
+            }
+        }
+        if self.finished {
+            return None;
+        }
+        self.finished = true;
+        self.lexeme.clear();
+        let mut eof = self.add_token(TokenType::Eof);
+        if self.keep_trivia {
+            eof.trivia = Some(Box::new(TokenTrivia {
+                leading: std::mem::take(&mut self.pending_trivia),
+                trailing: Vec::new(),
+            }));
+        }
+        Some(Ok(eof))
     }
 }
 
-pub fn error(token: Token, message: &str) {
-    if token.ttype == TokenType::Eof {
-        report(token.line, " at end", message);
+pub fn error(token: &Span, message: &str) {
+    report_at(token.line, token.ttype, token.lexeme.as_str(), message);
+}
+
+/// Like `error`, for a token that hasn't become a span, which still has the
+/// text of a number.
+pub fn token_error(token: &Token, message: &str) {
+    report_at(token.line, token.ttype, token.lexeme.as_str(), message);
+}
+
+fn report_at(line: usize, ttype: TokenType, lexeme: &str, message: &str) {
+    if ttype == TokenType::Eof {
+        report(line, " at end", message);
+    } else {
+        report(line, &format!(" at ' {} '", lexeme), message);
     }
-    report(token.line, &format!(" at ' {} '", token.lexeme), &message);
 }
 
 fn report(line: usize, position: &str, message: &str) {
@@ -311,13 +717,13 @@ fn report(line: usize, position: &str, message: &str) {
 }
 
 fn is_digit(c: char) -> bool {
-    return c >= '0' && c <= '9';
+    c.is_ascii_digit()
 }
 
 fn is_alpha(c: char) -> bool {
-    return c >= 'a' && c <= 'z' || c >= 'A' && c <= 'Z' || c == '_';
+    c.is_ascii_alphabetic() || c == '_'
 }
 
 fn is_alphanumeric(c: char) -> bool {
-    return is_alpha(c) || is_digit(c);
+    is_alpha(c) || is_digit(c)
 }
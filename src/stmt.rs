@@ -1,4 +1,12 @@
-use crate::{expr::Expr, scanner::Token};
+use crate::{
+    expr::Expr,
+    resolver::{Layout, Slot},
+    scanner::Span,
+};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 
 #[derive(Clone, Debug)]
 pub enum Stmt {
@@ -8,7 +16,14 @@ pub enum Stmt {
     Var(Var),
     If(If),
     While(Box<While>),
-    Function(Box<Function>),
+    ForIn(Box<ForIn>),
+    Break(Break),
+    Continue(Continue),
+    Function(Rc<Function>),
+    Return(Return),
+    Import(Import),
+    Throw(Throw),
+    Try(Box<Try>),
 }
 
 #[derive(Clone, Debug)]
@@ -23,8 +38,10 @@ pub struct Print {
 
 #[derive(Clone, Debug)]
 pub struct Var {
-    pub name: Token,
+    pub name: Span,
     pub initializer: Option<Expr>,
+    /// Set by the resolver, like the slots of the other declarations.
+    pub slot: Cell<Slot>,
 }
 
 #[derive(Clone, Debug)]
@@ -43,11 +60,78 @@ pub struct If {
 pub struct While {
     pub condition: Expr,
     pub body: Stmt,
+    /// The increment clause of a desugared `for` loop. It runs after every
+    /// iteration, including ones cut short by `continue`.
+    pub increment: Option<Expr>,
+}
+
+/// `for (var name in iterable) body`
+#[derive(Clone, Debug)]
+pub struct ForIn {
+    pub name: Span,
+    pub keyword: Span,
+    pub iterable: Expr,
+    pub body: Stmt,
+    pub slot: Cell<Slot>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Break {
+    pub keyword: Span,
+}
+
+#[derive(Clone, Debug)]
+pub struct Continue {
+    pub keyword: Span,
 }
 
 #[derive(Clone, Debug)]
 pub struct Function {
-    pub name: Token,
-    pub parameters: Vec<Token>,
+    /// `None` for function expressions.
+    pub name: Option<Span>,
+    pub parameters: Vec<Span>,
     pub body: Vec<Stmt>,
+    /// The slot of the declared name.
+    pub slot: Cell<Slot>,
+    /// Set by the resolver.
+    pub layout: RefCell<Layout>,
+}
+
+/// `import "path.lox" as name;`. Without `as`, the module is bound to the
+/// file name without its extension.
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub keyword: Span,
+    pub path: Span,
+    pub name: Option<Span>,
+    pub slot: Cell<Slot>,
+}
+
+/// `throw value;`
+#[derive(Clone, Debug)]
+pub struct Throw {
+    pub keyword: Span,
+    pub value: Expr,
+}
+
+/// `try { body } catch (name) { ... } finally { ... }`. The parser makes sure
+/// at least one of `catch` and `finally` is present.
+#[derive(Clone, Debug)]
+pub struct Try {
+    pub body: Vec<Stmt>,
+    pub catch: Option<Catch>,
+    pub finally: Option<Vec<Stmt>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Catch {
+    pub name: Span,
+    pub body: Vec<Stmt>,
+    pub slot: Cell<Slot>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Return {
+    pub keyword: Span,
+    pub value: Option<Expr>,
 }
@@ -0,0 +1,129 @@
+//! Interned strings.
+//!
+//! The scanner interns identifiers and string literals, so names are compared
+//! and hashed as small integers from then on: tokens are cheap to clone,
+//! environments are keyed by `Symbol`, and two string literals with the same
+//! text share one `Str` at runtime, which makes comparing them a pointer
+//! check.
+//!
+//! Interned text lives for the rest of the program, so nothing else is
+//! interned: numbers keep their own text, keywords and punctuation use
+//! `Symbol::from_static`, which doesn't copy, and strings built at runtime or
+//! by the optimizer stay ordinary `Str`s.
+//!
+//! Each thread has its own table, so a `Symbol` is only meaningful on the
+//! thread that made it and is neither `Send` nor `Sync`.
+
+use crate::value::Str;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    hash::{BuildHasherDefault, Hasher},
+    marker::PhantomData,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32, PhantomData<*const ()>);
+
+/// A map keyed by symbols. Symbols are already unique integers, so hashing
+/// them only needs to spread the bits.
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
+
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(self.0.rotate_left(8) ^ byte as u64);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write_u64(n as u64);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+    /// The runtime value of each symbol, made the first time it is needed.
+    values: Vec<Option<Str>>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+        ids: HashMap::from([("", Symbol::EMPTY)]),
+        names: vec![""],
+        values: vec![None],
+    });
+}
+
+impl Symbol {
+    pub const EMPTY: Symbol = Symbol(0, PhantomData);
+
+    /// The symbol for `text`, copying it into the table the first time it
+    /// is seen.
+    pub fn intern(text: &str) -> Symbol {
+        Symbol::insert(text, |text| Box::leak(text.into()))
+    }
+
+    /// Like `intern`, for text that is already static, such as the fixed
+    /// lexemes of keywords and punctuation.
+    pub fn from_static(text: &'static str) -> Symbol {
+        Symbol::insert(text, |_| text)
+    }
+
+    fn insert(text: &str, keep: impl FnOnce(&str) -> &'static str) -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if let Some(&symbol) = interner.ids.get(text) {
+                return symbol;
+            }
+            let name = keep(text);
+            let symbol = Symbol(interner.names.len() as u32, PhantomData);
+            interner.ids.insert(name, symbol);
+            interner.names.push(name);
+            interner.values.push(None);
+            symbol
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize])
+    }
+
+    /// The symbol's text as a string value. Every call returns the same
+    /// allocation.
+    pub fn to_str(self) -> Str {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            let index = self.0 as usize;
+            let name = interner.names[index];
+            interner.values[index]
+                .get_or_insert_with(|| Str::from(name))
+                .clone()
+        })
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
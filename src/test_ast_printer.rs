@@ -3,23 +3,13 @@ use super::*;
 
 #[test]
 fn test_expr_printing() {
-    let minus = scanner::Token {
-        lexeme: String::from("-"),
-        line: 0,
-        literal: Some(scanner::Literal::Str(String::from(""))),
-        ttype: scanner::TokenType::Minus,
-    };
+    let minus = scanner::Span::synthetic(scanner::TokenType::Minus, "-", 0);
     let num1 = scanner::Literal::Number(666.666);
     let unary = expr::Unary {
         operator: minus,
         right: expr::Expr::Literal(num1),
     };
-    let star = scanner::Token {
-        lexeme: String::from("*"),
-        line: 1,
-        literal: Some(scanner::Literal::Str(String::from(""))),
-        ttype: scanner::TokenType::Star,
-    };
+    let star = scanner::Span::synthetic(scanner::TokenType::Star, "*", 1);
     let num2 = scanner::Literal::Number(45.67);
     let grouping = expr::Grouping {
         expression: expr::Expr::Literal(num2),
@@ -33,3 +23,25 @@ fn test_expr_printing() {
     print!("{}", expr.print());
     assert_eq!(expr.print(), "(* (- 666.666) (group 45.67))")
 }
+
+#[test]
+fn test_conditional_printing() {
+    let conditional = expr::Conditional {
+        condition: expr::Expr::Literal(scanner::Literal::Bool(true)),
+        then_branch: expr::Expr::Literal(scanner::Literal::Number(1.0)),
+        else_branch: expr::Expr::Literal(scanner::Literal::Nil),
+    };
+    let expr = expr::Expr::Conditional(Box::new(conditional));
+    assert_eq!(expr.print(), "(?: true 1 nil)")
+}
+
+#[test]
+fn test_function_printing() {
+    let source = "fun (a, b) { if (a and b) return f(a, b); x = a or b; };";
+    let scanner = scanner::Scanner::new(source.as_bytes());
+    let statements = parser::Parser::new(scanner).parse().unwrap();
+    assert_eq!(
+        statements[0].print(),
+        "(; (fun (a b) (if (and a b) (return (call f a b))) (; (= x (or a b)))))"
+    )
+}
@@ -0,0 +1,473 @@
+#[cfg(test)]
+use super::*;
+
+#[cfg(test)]
+fn run(source: &str) -> interpreter::Interpreter {
+    let mut parser = parser::Parser::new(scanner::Scanner::new(source.as_bytes()));
+    let mut interpreter = interpreter::Interpreter::new();
+    interpreter.interpret(parser.parse().unwrap());
+    interpreter
+}
+
+#[cfg(test)]
+fn global(interpreter: &interpreter::Interpreter, name: &str) -> String {
+    interpreter.env.borrow().values[&symbol::Symbol::intern(name)].to_string()
+}
+
+#[test]
+fn test_compound_assignment_and_modulo() {
+    let interpreter = run("
+        var n = 10;
+        n += 5; n -= 3; n *= 2; n /= 4; n %= 4;
+        var a = -7 % 3;
+        var b = 7 % -3;
+        var c = 5.5 % 2;
+        var d = -5.5 % 2;");
+    assert_eq!(global(&interpreter, "n"), "2");
+    assert_eq!(global(&interpreter, "a"), "-1");
+    assert_eq!(global(&interpreter, "b"), "1");
+    assert_eq!(global(&interpreter, "c"), "1.5");
+    assert_eq!(global(&interpreter, "d"), "-1.5");
+
+    let statements = parser::Parser::new(scanner::Scanner::new("xs[1] *= 5;".as_bytes()))
+        .parse()
+        .unwrap();
+    assert!(matches!(
+        &statements[0],
+        stmt::Stmt::Expression(stmt::Expression { expression: expr::Expr::SetIndex(set) })
+            if set.operator.as_ref().map(|operator| operator.ttype) == Some(scanner::TokenType::Star)
+    ));
+    let interpreter = run("
+        var xs = [1, 2];
+        xs[1] *= 5; xs[0] -= 3;
+        var m = {\"k\": 4};
+        m[\"k\"] /= 2; m[\"k\"] %= 3;
+        var s = \"a\";
+        var error;
+        try { s %= 1; } catch (e) { error = e.message; }");
+    assert_eq!(global(&interpreter, "xs"), "[-2, 10]");
+    assert_eq!(global(&interpreter, "m"), "{k: 2}");
+    assert_eq!(global(&interpreter, "s"), "a");
+    assert!(global(&interpreter, "error").starts_with("Operands must be numbers."));
+
+    let mut interpreter = run("var s = \"a\";");
+    let statements = parser::Parser::new(scanner::Scanner::new("s %= 1;".as_bytes()))
+        .parse()
+        .unwrap();
+    let stmt::Stmt::Expression(statement) = &statements[0] else {
+        panic!("Expected an expression statement.");
+    };
+    let error = interpreter.evaluate(&statement.expression).unwrap_err();
+    assert!(error.to_string().starts_with("Operands must be numbers."));
+    assert_eq!(global(&interpreter, "s"), "a");
+}
+
+#[test]
+fn test_loops_break_and_continue() {
+    let interpreter = run("
+        var sum = 0;
+        for (var i = 0; i < 10; i += 1) {
+            if (i % 2 == 1) continue;
+            if (i > 6) break;
+            sum += i;
+        }
+        var count = 0;
+        while (true) { { count += 1; if (count == 3) break; } }");
+    assert_eq!(global(&interpreter, "sum"), "12");
+    assert_eq!(global(&interpreter, "count"), "3");
+}
+
+#[test]
+fn test_break_and_continue_outside_a_loop() {
+    let parse =
+        |source: &str| parser::Parser::new(scanner::Scanner::new(source.as_bytes())).parse();
+    assert!(parse("{ break; }").is_err());
+    assert!(parse("if (true) continue;").is_err());
+    assert!(parse("while (true) { fun f() { break; } }").is_err());
+    assert!(parse("fun f() { { continue; } }").is_err());
+    assert!(parse("while (true) { { break; } }").is_ok());
+
+    // A loop inside a function inside a loop keeps its own `break`.
+    let interpreter = run("
+        var seen = [];
+        for (var i in range(3)) {
+            fun first_even(xs) {
+                for (var x in xs) { { if (x % 2 == 1) continue; } if (x > 0) return x; }
+                return nil;
+            }
+            if (i == 2) { { break; } }
+            push(seen, first_even([1, 3, i + 2]));
+        }");
+    assert_eq!(global(&interpreter, "seen"), "[2, nil]");
+}
+
+#[test]
+fn test_locals_and_captured_variables() {
+    let interpreter = run("
+        var fs = [];
+        for (var i in range(3)) push(fs, fun () { return i; });
+        var seen = map(fs, fun (f) { return f(); });
+        fun counter() {
+            var n = 0;
+            return fun () { n += 1; return n; };
+        }
+        var next = counter();
+        next();
+        var count = next();
+        var shadowed;
+        {
+            var x = 1;
+            { var x = x + 1; shadowed = x; }
+            shadowed = [shadowed, x];
+        }");
+    assert_eq!(global(&interpreter, "seen"), "[0, 1, 2]");
+    assert_eq!(global(&interpreter, "count"), "2");
+    assert_eq!(global(&interpreter, "shadowed"), "[2, 1]");
+}
+
+#[test]
+fn test_lists_are_shared_and_mutable() {
+    let interpreter = run("
+        var xs = [1, 2, 3];
+        var ys = xs;
+        ys[0] += 10;
+        push(ys, xs[1] * 2);
+        var last = pop(xs);
+        var size = len(ys);");
+    assert_eq!(global(&interpreter, "xs"), "[11, 2, 3]");
+    assert_eq!(global(&interpreter, "last"), "4");
+    assert_eq!(global(&interpreter, "size"), "3");
+}
+
+#[test]
+fn test_maps_keep_insertion_order() {
+    let interpreter = run("
+        var m = {\"b\": 1, 2: \"two\", nil: true};
+        m[\"b\"] += 1;
+        m[\"a\"] = [];
+        var removed = remove(m, 2);
+        var found = has(m, nil) and !has(m, 2);
+        var ks = keys(m);");
+    assert_eq!(global(&interpreter, "m"), "{b: 2, nil: true, a: []}");
+    assert_eq!(global(&interpreter, "removed"), "two");
+    assert_eq!(global(&interpreter, "found"), "true");
+    assert_eq!(global(&interpreter, "ks"), "[b, nil, a]");
+}
+
+#[test]
+fn test_for_in_over_builtin_iterables() {
+    let interpreter = run("
+        var seen = [];
+        for (var x in [1, 2]) push(seen, x);
+        for (var k in {\"a\": 1}) push(seen, k);
+        for (var c in \"hé\") push(seen, c);
+        for (var i in range(6, 0, -2)) {
+            if (i == 4) continue;
+            push(seen, i);
+        }");
+    assert_eq!(global(&interpreter, "seen"), "[1, 2, a, h, é, 6, 2]");
+}
+
+#[test]
+fn test_for_in_over_objects_with_next() {
+    let source = "fun countdown(n) {
+  return {\"next\": () => (n > 0) ? (n -= 1) + 1 : nil};
+}
+var seen = [];
+for (var x in countdown(3)) push(seen, x);
+for (var x in countdown(5)) { if (x == 3) break; push(seen, x); }
+var failing = {\"next\": () => 1 - \"a\"};
+var line;
+try {
+  for (var x in failing) {}
+} catch (e) { line = e.line; }
+var data = [];
+for (var k in {\"next\": 1}) push(data, k);";
+    for use_vm in [false, true] {
+        let mut parser = parser::Parser::new(scanner::Scanner::new(source.as_bytes()));
+        let mut interpreter = interpreter::Interpreter::new();
+        let statements = parser.parse().unwrap();
+        match use_vm {
+            true => vm::interpret(&mut interpreter, &statements),
+            false => interpreter.interpret(statements),
+        }
+        assert_eq!(global(&interpreter, "seen"), "[3, 2, 1, 5, 4]");
+        assert_eq!(global(&interpreter, "line"), "7");
+        assert_eq!(global(&interpreter, "data"), "[next]");
+    }
+}
+
+#[test]
+fn test_string_builtins_count_characters() {
+    let interpreter = run("
+        var s = \"héllo wörld\";
+        var parts = split(s, \" \");
+        var word = substr(s, 6, 5);
+        var at = index_of(s, \"wö\") + len(s);
+        var code = chr(ord(char_at(s, 1)));
+        var n = num(\" 2.5 \") * 2;");
+    assert_eq!(global(&interpreter, "parts"), "[héllo, wörld]");
+    assert_eq!(global(&interpreter, "word"), "wörld");
+    assert_eq!(global(&interpreter, "at"), "17");
+    assert_eq!(global(&interpreter, "code"), "é");
+    assert_eq!(global(&interpreter, "n"), "5");
+}
+
+#[test]
+fn test_seeded_random_is_reproducible() {
+    let source = "
+        seed(1234);
+        var rolls = [];
+        for (var i in range(5)) push(rolls, random_int(1, 6));
+        var x = random();
+        var m = max(rolls) <= 6 and min(rolls) >= 1 and x >= 0 and x < 1;";
+    let first = run(source);
+    let second = run(source);
+    assert_eq!(global(&first, "rolls"), global(&second, "rolls"));
+    assert_eq!(global(&first, "x"), global(&second, "x"));
+    assert_eq!(global(&first, "m"), "true");
+}
+
+#[test]
+fn test_io_builtins_need_capabilities() {
+    let path = std::env::temp_dir().join("rlox_test_io_capabilities.txt");
+    let source = format!(
+        "var denied = true; write_file(\"{0}\", \"a\"); denied = false; append_file(\"{0}\", \"b\"); var text = read_file(\"{0}\");",
+        path.display()
+    );
+    let statements = || {
+        parser::Parser::new(scanner::Scanner::new(source.as_bytes()))
+            .parse()
+            .unwrap()
+    };
+
+    let mut sandboxed = interpreter::Interpreter::new();
+    sandboxed.interpret(statements());
+    assert_eq!(global(&sandboxed, "denied"), "true");
+
+    let mut trusted = interpreter::Interpreter::new();
+    trusted.capabilities = native_io::Capabilities::all();
+    trusted.interpret(statements());
+    assert_eq!(global(&trusted, "denied"), "false");
+    assert_eq!(global(&trusted, "text"), "ab");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_exit_stops_the_script() {
+    let interpreter = run("var reached = false; exit(7); reached = true;");
+    assert_eq!(interpreter.exit_code, Some(7));
+    assert_eq!(global(&interpreter, "reached"), "false");
+
+    let interpreter = run("var errors = [];
+for (var code in [4294967296, -2147483649, 1.5, \"1\"]) {
+  try { exit(code); } catch (e) { push(errors, e.message); }
+}");
+    assert_eq!(interpreter.exit_code, None);
+    let errors = global(&interpreter, "errors");
+    assert_eq!(errors.matches("exit()").count(), 4);
+    assert!(errors.contains("exit() code 4294967296 is out of range."));
+    assert!(errors.contains("exit() code -2147483649 is out of range."));
+    assert!(errors.contains("exit() expects an integer, got 1.5."));
+}
+
+#[test]
+fn test_import_runs_each_module_once() {
+    let dir = std::env::temp_dir().join("rlox_test_import");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("util.lox"),
+        "var answer = 6 * 7; fun twice(x) { return x * 2; }",
+    )
+    .unwrap();
+    std::fs::write(dir.join("a.lox"), "import \"b.lox\";").unwrap();
+    std::fs::write(dir.join("b.lox"), "import \"a.lox\";").unwrap();
+
+    let source = "import \"util.lox\"; import \"util.lox\" as u; var answer = util.answer; var doubled = util.twice(21); var same = util == u; var cycle = true; import \"a.lox\"; cycle = false;";
+    let mut interpreter = interpreter::Interpreter::new();
+    interpreter.capabilities = native_io::Capabilities::all();
+    interpreter.importing.push(dir.join("main.lox"));
+    interpreter.interpret(
+        parser::Parser::new(scanner::Scanner::new(source.as_bytes()))
+            .parse()
+            .unwrap(),
+    );
+    assert_eq!(global(&interpreter, "answer"), "42");
+    assert_eq!(global(&interpreter, "doubled"), "42");
+    assert_eq!(global(&interpreter, "same"), "true");
+    assert_eq!(global(&interpreter, "cycle"), "true");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_try_catch_finally() {
+    let interpreter = run("var log = [];
+try { push(log, 1); throw \"boom\"; } catch (e) { push(log, e); } finally { push(log, 2); }
+var line;
+try { var x = 1 - \"a\"; } catch (e) { line = e.line; }
+for (var i in range(3)) { try { if (i == 1) break; } finally { push(log, i); } }");
+    assert_eq!(global(&interpreter, "log"), "[1, boom, 2, 0, 1]");
+    assert_eq!(global(&interpreter, "line"), "4");
+}
+
+#[test]
+fn test_function_expressions() {
+    let interpreter = run("fun apply(f, x) { return f(x); }
+var inc = apply(fun (x) { return x + 1; }, 41);
+fun counter() { var n = 0; return fun () { n = n + 1; return n; }; }
+var next = counter(); next();
+var count = next();
+var anonymous = fun () {};");
+    assert_eq!(global(&interpreter, "inc"), "42");
+    assert_eq!(global(&interpreter, "count"), "2");
+    assert_eq!(global(&interpreter, "anonymous"), "<fn>");
+}
+
+#[test]
+fn test_deep_recursion_is_a_catchable_error() {
+    let parse =
+        |source: &str| parser::Parser::new(scanner::Scanner::new(source.as_bytes())).parse();
+    assert!(parse("fun f() { var 1; }").is_err());
+
+    let source = "fun r(n) { return r(n + 1); }
+var caught;
+try { r(0); } catch (e) { caught = e.message; }
+var after = map([1], (x) => x + 1);";
+    // Like the command line, run with enough native stack for the deepest
+    // calls the interpreter allows.
+    let runner = std::thread::Builder::new()
+        .stack_size(64 << 20)
+        .spawn(move || {
+            for use_vm in [false, true] {
+                let mut interpreter = interpreter::Interpreter::new();
+                let statements = parse(source).unwrap();
+                match use_vm {
+                    true => vm::interpret(&mut interpreter, &statements),
+                    false => interpreter.interpret(statements),
+                }
+                assert!(global(&interpreter, "caught").starts_with("Stack overflow."));
+                assert_eq!(global(&interpreter, "after"), "[2]");
+            }
+        });
+    runner.unwrap().join().unwrap();
+}
+
+#[test]
+fn test_arrow_functions_and_list_helpers() {
+    let interpreter = run("var xs = [3, 1, 2];
+var doubled = map(xs, (x) => x * 2);
+var big = filter(xs, (x) => x > 1);
+var sum = reduce(xs, (a, b) => a + b, 0);
+var sorted = sort_by([\"bb\", \"a\", \"ccc\"], (s) => len(s));
+var some = any(xs, (x) => x == 2);
+var every = all(xs, (x) => x > 1);
+var grouped = (1 + 2) * 3;
+var line;
+try { map(xs, (x) =>
+  x - \"a\"); } catch (e) { line = e.line; }");
+    assert_eq!(global(&interpreter, "doubled"), "[6, 2, 4]");
+    assert_eq!(global(&interpreter, "big"), "[3, 2]");
+    assert_eq!(global(&interpreter, "sum"), "6");
+    assert_eq!(global(&interpreter, "sorted"), "[a, bb, ccc]");
+    assert_eq!(global(&interpreter, "some"), "true");
+    assert_eq!(global(&interpreter, "every"), "false");
+    assert_eq!(global(&interpreter, "grouped"), "9");
+    assert_eq!(global(&interpreter, "line"), "11");
+}
+
+#[test]
+fn test_collector_frees_cycles() {
+    let source = "fun make() {
+  var self;
+  self = fun () { return self; };
+  var xs = [];
+  push(xs, xs);
+  var m = {};
+  m[\"m\"] = m;
+}
+for (var i in range(100)) make();
+var kept = [];
+push(kept, kept);";
+    for use_vm in [false, true] {
+        let mut parser = parser::Parser::new(scanner::Scanner::new(source.as_bytes()));
+        let mut interpreter = interpreter::Interpreter::new();
+        let statements = parser.parse().unwrap();
+        match use_vm {
+            true => vm::interpret(&mut interpreter, &statements),
+            false => interpreter.interpret(statements),
+        }
+        assert!(interpreter.collect() >= 400);
+        assert_eq!(interpreter.collect(), 0);
+        assert!(interpreter.heap.len() < 10);
+        let kept = interpreter.env.borrow().values[&symbol::Symbol::intern("kept")].get();
+        assert!(matches!(kept, interpreter::Data::List(list) if list.borrow().len() == 1));
+    }
+}
+
+#[test]
+fn test_collector_frees_strings_in_cycles() {
+    let source = "fun make(n) {
+  var m = {};
+  for (var i in range(n)) m[\"k\" + str(i)] = upper(\"v\") + str(i);
+  m[\"self\"] = m;
+  var parts = split(\"a,b,c\", \",\");
+  push(parts, parts);
+}
+for (var i in range(20)) make(5);
+var kept = {};
+for (var c in \"xy\") kept[c + c] = kept;
+var names = join(keys(kept), \",\");";
+    for use_vm in [false, true] {
+        let mut parser = parser::Parser::new(scanner::Scanner::new(source.as_bytes()));
+        let mut interpreter = interpreter::Interpreter::new();
+        let statements = parser.parse().unwrap();
+        match use_vm {
+            true => vm::interpret(&mut interpreter, &statements),
+            false => interpreter.interpret(statements),
+        }
+        assert!(interpreter.collect() >= 20 * 25);
+        assert_eq!(interpreter.collect(), 0);
+        assert!(interpreter.heap.len() < 20);
+        assert_eq!(global(&interpreter, "names"), "xx,yy");
+    }
+}
+
+#[test]
+fn test_stress_collection_keeps_live_values() {
+    let source = "fun pair(a, b) { return [a, b]; }
+var sum = len(pair(1, [2])) + len(pair([3], 4));
+var nested = [[1], pair(2, 3), {\"k\": [4], \"p\": map([5], (x) => pair(x, [x]))}];
+var lists = map([1, 2], (x) => [x, [x * 2]]);
+fun f() { try { return [\"r\" + str(1)]; } finally { var junk = [1, 2]; } }
+var returned = f();
+var chars = \"\";
+for (var c in [\"a\" + \"b\", \"c\" + \"d\"]) chars = chars + c + str(len([c]));
+var m = {\"k\" + str(1): [1], \"j\": {}};
+var words = reduce([\"x\", \"y\"], (a, b) => a + upper(b), \"\");
+var sorted = sort_by([[3], [1], [2]], (xs) => str(xs[0]));
+var caught;
+try { [1][\"a\" + \"b\"]; } catch (e) { caught = str(e.line) + \"!\"; }";
+    let names = [
+        "sum", "nested", "lists", "returned", "chars", "m", "words", "sorted", "caught",
+    ];
+    let expected = run(source);
+    assert_eq!(global(&expected, "chars"), "ab1cd1");
+    for use_vm in [false, true] {
+        let mut parser = parser::Parser::new(scanner::Scanner::new(source.as_bytes()));
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.heap.stress = true;
+        let statements = parser.parse().unwrap();
+        match use_vm {
+            true => vm::interpret(&mut interpreter, &statements),
+            false => interpreter.interpret(statements),
+        }
+        for name in names {
+            assert_eq!(
+                global(&interpreter, name),
+                global(&expected, name),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,59 @@
+#[cfg(test)]
+use super::*;
+
+#[cfg(test)]
+fn optimize(source: &str) -> Vec<stmt::Stmt> {
+    let statements = parser::Parser::new(scanner::Scanner::new(source.as_bytes()))
+        .parse()
+        .unwrap();
+    optimizer::optimize(statements)
+}
+
+#[test]
+fn test_optimizer_folds_constants() {
+    let statements = optimize(
+        "print (1 + 2) * 3 == 9 and \"a\" + \"b\";
+print false or nil ? -1 : !nil;
+print (1 + 1) * \"a\";
+print 1 - \"a\";",
+    );
+    let printed: Vec<String> = statements
+        .iter()
+        .map(|statement| match statement {
+            stmt::Stmt::Print(print) => print.expression.print(),
+            other => panic!("Expected a print statement, got {:?}", other),
+        })
+        .collect();
+    assert_eq!(printed, ["ab", "true", "(* 2 a)", "(- 1 a)"]);
+}
+
+#[test]
+fn test_optimizer_drops_dead_code_and_keeps_error_lines() {
+    let source = "var log = [];
+if (false) push(log, 1); else push(log, 2);
+if (nil) push(log, 3);
+while (false) push(log, 4);
+for (var i = 0; false; i += 1) push(log, 5);
+var line;
+try {
+  if (true) {
+    var x = (2 * 3) -
+      \"a\";
+  }
+} catch (e) { line = e.line; }";
+    let statements = optimize(source);
+    assert_eq!(statements.len(), 5);
+    for use_vm in [false, true] {
+        let mut interpreter = interpreter::Interpreter::new();
+        match use_vm {
+            true => vm::interpret(&mut interpreter, &statements),
+            false => interpreter.interpret(statements.clone()),
+        }
+        let global = |name| {
+            let env = interpreter.env.borrow();
+            env.values[&symbol::Symbol::intern(name)].to_string()
+        };
+        assert_eq!(global("log"), "[2]");
+        assert_eq!(global("line"), "9");
+    }
+}
@@ -0,0 +1,130 @@
+#[cfg(test)]
+use super::*;
+
+#[test]
+fn test_scanner_streams_tokens() {
+    let mut scanner = scanner::Scanner::new("var x = 1.5;".as_bytes());
+    let first = scanner.next().unwrap().unwrap();
+    assert_eq!(first.ttype, scanner::TokenType::Var);
+
+    let rest: Vec<scanner::TokenType> = scanner.map(|t| t.unwrap().ttype).collect();
+    assert_eq!(
+        rest,
+        vec![
+            scanner::TokenType::Identifier,
+            scanner::TokenType::Equal,
+            scanner::TokenType::Number,
+            scanner::TokenType::Semicolon,
+            scanner::TokenType::Eof,
+        ]
+    );
+}
+
+#[test]
+fn test_scanner_reports_errors_and_continues() {
+    let results: Vec<_> = scanner::Scanner::new("\"open\n# 1".as_bytes()).collect();
+    assert!(matches!(
+        results[0],
+        Err(scanner::Error::UnterminatedString(2))
+    ));
+    assert_eq!(results.len(), 2);
+
+    let results: Vec<_> = scanner::Scanner::new("# 1".as_bytes()).collect();
+    assert!(matches!(
+        results[0],
+        Err(scanner::Error::UnexpectedCharacter(1, '#'))
+    ));
+    assert_eq!(
+        results[1].as_ref().unwrap().ttype,
+        scanner::TokenType::Number
+    );
+}
+
+#[test]
+fn test_parser_pulls_statements_lazily() {
+    let scanner = scanner::Scanner::new("var a = 1; print a;".as_bytes());
+    let mut parser = parser::Parser::new(scanner);
+    assert!(matches!(parser.next(), Some(Ok(stmt::Stmt::Var(_)))));
+    assert!(matches!(parser.next(), Some(Ok(stmt::Stmt::Print(_)))));
+    assert!(parser.next().is_none());
+
+    // A statement with a scan error in it isn't run, and neither is a script.
+    let scanner = scanner::Scanner::new("print 1; print # 2; print 3;".as_bytes());
+    let mut parser = parser::Parser::new(scanner);
+    assert!(matches!(parser.next(), Some(Ok(stmt::Stmt::Print(_)))));
+    assert!(matches!(
+        parser.next(),
+        Some(Err(parser::Error::ParseError))
+    ));
+    assert!(matches!(parser.next(), Some(Ok(stmt::Stmt::Print(_)))));
+    let scanner = scanner::Scanner::new("print 1; print # 2;".as_bytes());
+    assert!(parser::Parser::new(scanner).parse().is_err());
+}
+
+#[test]
+fn test_scanner_trivia_round_trip() {
+    let source = "// header\n\nvar x = 1;  // one\n\tprint x;\n@ \"é\" ☃\n// trailing";
+    let tokens = scanner::Scanner::new(source.as_bytes())
+        .with_trivia()
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>();
+    assert_eq!(scanner::to_source(&tokens), source);
+
+    assert_eq!(
+        tokens[0].trivia.as_ref().unwrap().leading,
+        vec![
+            scanner::Trivia::Comment(String::from("// header")),
+            scanner::Trivia::Newline,
+            scanner::Trivia::Newline,
+        ]
+    );
+    assert_eq!(
+        tokens[4].trivia.as_ref().unwrap().trailing,
+        vec![
+            scanner::Trivia::Whitespace(String::from("  ")),
+            scanner::Trivia::Comment(String::from("// one")),
+            scanner::Trivia::Newline,
+        ]
+    );
+    assert_eq!(tokens[4].line, 3);
+}
+
+#[test]
+fn test_scanner_interns_lexemes() {
+    let tokens: Vec<scanner::Token> = scanner::Scanner::new("x \"s\" x \"s\" 1.50".as_bytes())
+        .map(|t| t.unwrap())
+        .collect();
+    assert_eq!(tokens[0].lexeme, tokens[2].lexeme);
+    let x = symbol::Symbol::intern("x");
+    assert_eq!(tokens[0].lexeme, scanner::Lexeme::Symbol(x));
+    assert_eq!(tokens[0].lexeme.as_str(), "x");
+    // Numbers keep their exact text without going through the interner.
+    assert_eq!(tokens[4].lexeme, scanner::Lexeme::Number("1.50".into()));
+
+    let (Some(scanner::Literal::Str(first)), Some(scanner::Literal::Str(second))) =
+        (&tokens[1].literal, &tokens[3].literal)
+    else {
+        panic!("Expected string literals.");
+    };
+    assert!(crate::value::Str::ptr_eq(first, second));
+}
+
+#[test]
+fn test_spans_point_into_the_source() {
+    let source = "var s = \"hé\"; // note\nprint s + 1.5;";
+    let tokens = scanner::Scanner::new(source.as_bytes())
+        .scan_tokens()
+        .unwrap();
+    for token in &tokens {
+        let span = token.span();
+        assert_eq!(
+            &source[span.start as usize..span.end as usize],
+            token.lexeme.as_str()
+        );
+        // A number's span leaves its text to the syntax tree.
+        if token.ttype != scanner::TokenType::Number {
+            assert_eq!(span.to_string(), token.to_string());
+        }
+    }
+    assert_eq!(tokens.last().unwrap().start as usize, source.len());
+}
@@ -0,0 +1,40 @@
+#[cfg(test)]
+use super::*;
+
+#[test]
+fn test_values_round_trip_and_share_objects() {
+    use interpreter::Data;
+    use value::Value;
+
+    let list = std::rc::Rc::new(std::cell::RefCell::new(vec![Value::NIL]));
+    let samples = [
+        Data::Number(-0.0),
+        Data::Number(f64::INFINITY),
+        Data::Number(f64::NAN),
+        Data::Bool(false),
+        Data::Bool(true),
+        Data::Nil,
+        Data::Str("lox".into()),
+        Data::List(list.clone()),
+        Data::Range(iterator::Range {
+            start: 0.0,
+            end: 3.0,
+            step: 1.0,
+        }),
+    ];
+    let printed: Vec<String> = samples.iter().map(Data::to_string).collect();
+    let values: Vec<Value> = samples.into_iter().map(Value::from).collect();
+    let copies = values.clone();
+    assert_eq!(std::rc::Rc::strong_count(&list), 3);
+    let printed_back: Vec<String> = copies.iter().map(Value::to_string).collect();
+    assert_eq!(printed, printed_back);
+    assert_eq!(
+        values[0].as_number().map(f64::to_bits),
+        Some((-0.0f64).to_bits())
+    );
+    assert!(values[3].is_falsey() && values[5].is_falsey() && !values[4].is_falsey());
+    drop((values, copies));
+    assert_eq!(std::rc::Rc::strong_count(&list), 1);
+    #[cfg(feature = "nan-boxing")]
+    assert_eq!(std::mem::size_of::<Value>(), 8);
+}
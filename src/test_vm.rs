@@ -0,0 +1,148 @@
+#[cfg(test)]
+use super::*;
+
+#[test]
+fn test_opcodes_round_trip_through_bytes() {
+    for byte in 0..=u8::MAX {
+        if let Some(op) = chunk::OpCode::from_byte(byte) {
+            assert_eq!(op as u8, byte);
+        }
+    }
+    assert_eq!(
+        chunk::OpCode::from_byte(chunk::OpCode::PopHandler as u8),
+        Some(chunk::OpCode::PopHandler)
+    );
+}
+
+#[test]
+fn test_vm_closures_and_handlers() {
+    let source = "fun counter() {
+  var count = 0;
+  return () => count = count + 1;
+}
+var next = counter(); next();
+var count = next();
+var caught;
+for (var i in [1, 2]) {
+  try { throw i; } catch (e) { caught = e; } finally { count = count + 10; }
+}";
+    let mut parser = parser::Parser::new(scanner::Scanner::new(source.as_bytes()));
+    let mut interpreter = interpreter::Interpreter::new();
+    vm::interpret(&mut interpreter, &parser.parse().unwrap());
+    let globals = interpreter.env.borrow();
+    assert_eq!(globals.values[&symbol::Symbol::intern("count")].to_string(), "22");
+    assert_eq!(globals.values[&symbol::Symbol::intern("caught")].to_string(), "2");
+}
+
+#[test]
+fn test_disassembler_golden() {
+    let source = "var n = 2;
+while (n > 0) n = n - 1;
+fun id(x) { return x; }";
+    let mut parser = parser::Parser::new(scanner::Scanner::new(source.as_bytes()));
+    let script = compiler::compile(&parser.parse().unwrap()).unwrap();
+    let expected = "== <script> ==
+0000    1 Constant            0 '2'
+0003    | DefineGlobal        1 \"n\"
+0006    2 GetGlobal           1 \"n\"
+0009    | Constant            2 '0'
+0012    | Greater
+0013    | JumpIfFalse        15 -> 31
+0016    | Pop
+0017    | GetGlobal           1 \"n\"
+0020    | Constant            3 '1'
+0023    | Subtract
+0024    | SetGlobal           1 \"n\"
+0027    | Pop
+0028    | Loop               25 -> 6
+0031    | Pop
+0032    3 Closure             4 <fn id>
+0035    | DefineGlobal        5 \"id\"
+0038    | Nil
+0039    | Return
+
+== <fn id> ==
+0000    3 GetLocal            1
+0002    | Return
+0003    | Nil
+0004    | Return
+";
+    assert_eq!(disassembler::disassemble(&script), expected);
+}
+
+#[test]
+fn test_loxc_round_trip_and_rejection() {
+    let source =
+        "fun make(n) { var xs = []; for (var i in range(n)) push(xs, () => i); return xs; }
+print len(make(3));";
+    let mut parser = parser::Parser::new(scanner::Scanner::new(source.as_bytes()));
+    let script = compiler::compile(&parser.parse().unwrap()).unwrap();
+    let bytes = loxc::write(&script);
+
+    let loaded = loxc::read(&bytes).unwrap();
+    assert_eq!(
+        disassembler::disassemble(&loaded),
+        disassembler::disassemble(&script)
+    );
+
+    let mut version = bytes.clone();
+    version[5] += 1;
+    assert!(matches!(
+        loxc::read(&version),
+        Err(loxc::Error::Version(v)) if v == loxc::VERSION + 1
+    ));
+    let mut corrupted = bytes.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(matches!(loxc::read(&corrupted), Err(loxc::Error::Checksum)));
+    assert!(matches!(
+        loxc::read(&bytes[..7]),
+        Err(loxc::Error::Truncated)
+    ));
+    assert!(matches!(
+        loxc::read(b"print 1;"),
+        Err(loxc::Error::NotCompiled)
+    ));
+
+    // Index errors name the `]`, which the file must carry.
+    let mut parser = parser::Parser::new(scanner::Scanner::new("print [1][0];".as_bytes()));
+    let mut script = compiler::compile(&parser.parse().unwrap()).unwrap();
+    let loaded = loxc::read(&loxc::write(&script)).unwrap();
+    assert_eq!(loaded.chunk.spans, script.chunk.spans);
+    let chunk = &mut std::rc::Rc::get_mut(&mut script).unwrap().chunk;
+    chunk.spans.clear();
+    let err = loxc::read(&loxc::write(&script)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Compiled file is malformed: instruction has no source token"
+    );
+}
+
+#[test]
+fn test_loxc_rejects_bad_stack_use() {
+    let source = "{ var x = 1; print x; }";
+    let mut parser = parser::Parser::new(scanner::Scanner::new(source.as_bytes()));
+    let mut script = compiler::compile(&parser.parse().unwrap()).unwrap();
+    assert!(loxc::read(&loxc::write(&script)).is_ok());
+
+    let code = &mut std::rc::Rc::get_mut(&mut script).unwrap().chunk.code;
+    let get = code
+        .iter()
+        .position(|&byte| byte == chunk::OpCode::GetLocal as u8)
+        .unwrap();
+    code[get + 1] = 200;
+    let err = loxc::read(&loxc::write(&script)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Compiled file is malformed: local slot out of range"
+    );
+
+    // Popping `x` and the script itself leaves `print` nothing to print.
+    let code = &mut std::rc::Rc::get_mut(&mut script).unwrap().chunk.code;
+    code[get] = chunk::OpCode::Pop as u8;
+    code[get + 1] = chunk::OpCode::Pop as u8;
+    let err = loxc::read(&loxc::write(&script)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Compiled file is malformed: instruction pops more than is on the stack"
+    );
+}
@@ -0,0 +1,317 @@
+//! How the runtime stores values.
+//!
+//! Code that works on values uses `Data`, an enum that is easy to match on
+//! but four words wide. The places that hold many values at once — the
+//! stacks of both backends, environments, captured variables and list
+//! elements — store a `Value` instead and convert at the edges.
+//!
+//! By default a `Value` is just a `Data`. With the `nan-boxing` feature it is
+//! a single `u64`: numbers are stored as their bits, and everything else is
+//! packed into the payload of a quiet NaN, with a tag saying what the other
+//! 48 bits hold. Heap objects keep their `Rc` reference count, so the
+//! collector and `Data` see the same objects either way. Strings live on the
+//! heap as `Str`, a thin pointer in that configuration so they fit the box.
+
+use crate::interpreter::Data;
+use std::{fmt, ops::Deref, rc::Rc};
+
+/// An immutable string shared between every value that holds it.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Str(StrRc);
+
+#[cfg(not(feature = "nan-boxing"))]
+type StrRc = Rc<str>;
+/// `Rc<str>` is a fat pointer; boxing needs the address alone.
+#[cfg(feature = "nan-boxing")]
+type StrRc = Rc<Box<str>>;
+
+impl Str {
+    pub fn ptr_eq(this: &Str, other: &Str) -> bool {
+        Rc::ptr_eq(&this.0, &other.0)
+    }
+
+    /// The address of the shared allocation, which identifies the string.
+    pub fn as_ptr(this: &Str) -> *const () {
+        Rc::as_ptr(&this.0) as *const ()
+    }
+}
+
+impl Deref for Str {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Str {
+    fn from(str: &str) -> Str {
+        Str::from(String::from(str))
+    }
+}
+
+impl From<String> for Str {
+    fn from(string: String) -> Str {
+        #[cfg(not(feature = "nan-boxing"))]
+        return Str(Rc::from(string));
+        #[cfg(feature = "nan-boxing")]
+        return Str(Rc::new(string.into_boxed_str()));
+    }
+}
+
+impl fmt::Debug for Str {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl fmt::Display for Str {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self)
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+pub use self::boxed::Value;
+#[cfg(not(feature = "nan-boxing"))]
+pub use self::plain::Value;
+
+#[cfg(not(feature = "nan-boxing"))]
+mod plain {
+    use crate::interpreter::Data;
+
+    #[derive(Clone, Debug)]
+    pub struct Value(Data);
+
+    impl Value {
+        pub const NIL: Value = Value(Data::Nil);
+
+        /// A copy of the value, sharing any object it refers to.
+        #[inline]
+        pub fn get(&self) -> Data {
+            self.0.clone()
+        }
+
+        #[inline]
+        pub fn into_data(self) -> Data {
+            self.0
+        }
+
+        #[inline]
+        pub fn as_number(&self) -> Option<f64> {
+            match self.0 {
+                Data::Number(number) => Some(number),
+                _ => None,
+            }
+        }
+
+        #[inline]
+        pub fn is_falsey(&self) -> bool {
+            matches!(self.0, Data::Nil | Data::Bool(false))
+        }
+    }
+
+    impl From<Data> for Value {
+        #[inline]
+        fn from(data: Data) -> Value {
+            Value(data)
+        }
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+mod boxed {
+    use super::{Str, StrRc};
+    use crate::{
+        function::LoxFunction,
+        interpreter::{Data, ErrorObject},
+        iterator::Range,
+        map::LoxMap,
+        module::Module,
+        native::NativeFunction,
+        vm::Closure,
+    };
+    use std::{cell::RefCell, mem::ManuallyDrop, rc::Rc};
+
+    /// Every boxed value has these bits set. A number only does when it is a
+    /// NaN, and all NaNs are stored as exactly this pattern, so any other
+    /// value with them set is boxed.
+    const QNAN: u64 = 0x7ff8_0000_0000_0000;
+    const PAYLOAD: u64 = 0x0000_ffff_ffff_ffff;
+
+    // The tag is split between the sign bit and the three bits below the
+    // quiet bit. Tag 0 with an empty payload is the NaN itself.
+    const NIL: u64 = 1;
+    const FALSE: u64 = 2;
+    const TRUE: u64 = 3;
+    const STR: u64 = 4;
+    const FUNCTION: u64 = 5;
+    const CLOSURE: u64 = 6;
+    const NATIVE: u64 = 7;
+    const LIST: u64 = 8;
+    const MAP: u64 = 9;
+    const RANGE: u64 = 10;
+    const MODULE: u64 = 11;
+    const ERROR: u64 = 12;
+
+    const fn pack(tag: u64, payload: u64) -> u64 {
+        QNAN | (tag & 8) << 60 | (tag & 7) << 48 | payload
+    }
+
+    fn pointer<T>(pointer: *const T, tag: u64) -> u64 {
+        let address = pointer as usize as u64;
+        assert!(
+            address & !PAYLOAD == 0,
+            "heap address does not fit in 48 bits"
+        );
+        pack(tag, address)
+    }
+
+    pub struct Value(u64);
+
+    impl Value {
+        pub const NIL: Value = Value(pack(NIL, 0));
+
+        #[inline]
+        fn tag(&self) -> Option<u64> {
+            let tag = (self.0 >> 48 & 7) | (self.0 >> 60 & 8);
+            (self.0 & QNAN == QNAN && self.0 != QNAN).then_some(tag)
+        }
+
+        #[inline]
+        fn address(&self) -> usize {
+            (self.0 & PAYLOAD) as usize
+        }
+
+        /// A copy of the value, sharing any object it refers to.
+        #[inline]
+        pub fn get(&self) -> Data {
+            ManuallyDrop::new(self.clone()).take()
+        }
+
+        #[inline]
+        pub fn into_data(self) -> Data {
+            ManuallyDrop::new(self).take()
+        }
+
+        #[inline]
+        pub fn as_number(&self) -> Option<f64> {
+            match self.tag() {
+                None => Some(f64::from_bits(self.0)),
+                Some(_) => None,
+            }
+        }
+
+        #[inline]
+        pub fn is_falsey(&self) -> bool {
+            self.0 == pack(NIL, 0) || self.0 == pack(FALSE, 0)
+        }
+
+        /// Turns the value back into a `Data`, taking over the reference it
+        /// holds. The value must not be dropped afterwards.
+        #[inline]
+        fn take(&self) -> Data {
+            let address = self.address();
+            // SAFETY: a pointer tag is only ever packed with the address of
+            // an `Rc` of that type, and the value owns one strong count.
+            unsafe {
+                match self.tag() {
+                    None => Data::Number(f64::from_bits(self.0)),
+                    Some(NIL) => Data::Nil,
+                    Some(FALSE) => Data::Bool(false),
+                    Some(TRUE) => Data::Bool(true),
+                    Some(STR) => Data::Str(Str(Rc::from_raw(address as *const Box<str>))),
+                    Some(FUNCTION) => Data::Function(Rc::from_raw(address as *const LoxFunction)),
+                    Some(CLOSURE) => Data::Closure(Rc::from_raw(address as *const Closure)),
+                    Some(NATIVE) => Data::Native(Rc::from_raw(address as *const NativeFunction)),
+                    Some(LIST) => Data::List(Rc::from_raw(address as *const RefCell<Vec<Value>>)),
+                    Some(MAP) => Data::Map(Rc::from_raw(address as *const RefCell<LoxMap>)),
+                    Some(RANGE) => Data::Range(*Rc::from_raw(address as *const Range)),
+                    Some(MODULE) => Data::Module(Rc::from_raw(address as *const Module)),
+                    Some(ERROR) => Data::Error(Rc::from_raw(address as *const ErrorObject)),
+                    Some(tag) => unreachable!("invalid value tag {tag}"),
+                }
+            }
+        }
+    }
+
+    impl From<Data> for Value {
+        #[inline]
+        fn from(data: Data) -> Value {
+            Value(match data {
+                Data::Number(number) if number.is_nan() => QNAN,
+                Data::Number(number) => number.to_bits(),
+                Data::Nil => pack(NIL, 0),
+                Data::Bool(false) => pack(FALSE, 0),
+                Data::Bool(true) => pack(TRUE, 0),
+                Data::Str(Str(str)) => pointer(StrRc::into_raw(str), STR),
+                Data::Function(function) => pointer(Rc::into_raw(function), FUNCTION),
+                Data::Closure(closure) => pointer(Rc::into_raw(closure), CLOSURE),
+                Data::Native(native) => pointer(Rc::into_raw(native), NATIVE),
+                Data::List(list) => pointer(Rc::into_raw(list), LIST),
+                Data::Map(map) => pointer(Rc::into_raw(map), MAP),
+                // Ranges are three numbers, so they need a box of their own.
+                Data::Range(range) => pointer(Rc::into_raw(Rc::new(range)), RANGE),
+                Data::Module(module) => pointer(Rc::into_raw(module), MODULE),
+                Data::Error(error) => pointer(Rc::into_raw(error), ERROR),
+            })
+        }
+    }
+
+    impl Clone for Value {
+        #[inline]
+        fn clone(&self) -> Value {
+            let address = self.address();
+            // SAFETY: see `take`.
+            unsafe {
+                match self.tag() {
+                    None | Some(NIL | FALSE | TRUE) => {}
+                    Some(STR) => Rc::increment_strong_count(address as *const Box<str>),
+                    Some(FUNCTION) => Rc::increment_strong_count(address as *const LoxFunction),
+                    Some(CLOSURE) => Rc::increment_strong_count(address as *const Closure),
+                    Some(NATIVE) => Rc::increment_strong_count(address as *const NativeFunction),
+                    Some(LIST) => Rc::increment_strong_count(address as *const RefCell<Vec<Value>>),
+                    Some(MAP) => Rc::increment_strong_count(address as *const RefCell<LoxMap>),
+                    Some(RANGE) => Rc::increment_strong_count(address as *const Range),
+                    Some(MODULE) => Rc::increment_strong_count(address as *const Module),
+                    Some(ERROR) => Rc::increment_strong_count(address as *const ErrorObject),
+                    Some(tag) => unreachable!("invalid value tag {tag}"),
+                }
+            }
+            Value(self.0)
+        }
+    }
+
+    impl Drop for Value {
+        #[inline]
+        fn drop(&mut self) {
+            if !matches!(self.tag(), None | Some(NIL | FALSE | TRUE)) {
+                drop(self.take());
+            }
+        }
+    }
+
+    impl std::fmt::Debug for Value {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            self.get().fmt(f)
+        }
+    }
+}
+
+impl Default for Value {
+    fn default() -> Value {
+        Value::NIL
+    }
+}
+
+impl From<Value> for Data {
+    fn from(value: Value) -> Data {
+        value.into_data()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
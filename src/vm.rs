@@ -0,0 +1,650 @@
+use crate::{
+    chunk::{Capture, Constant, OpCode, Prototype},
+    compiler,
+    environment::Environment,
+    gc::Object,
+    interpreter::{
+        get_index, get_property, is_equal, map_key, set_index, Data, Error, Interpreter,
+        LoxCallable,
+    },
+    iterator::LoxIterator,
+    map::LoxMap,
+    native::{new_list, new_map},
+    scanner::{Span, TokenType},
+    stmt::Stmt,
+    symbol::Symbol,
+    value::Value,
+};
+use std::{cell::RefCell, fmt, ops::RangeInclusive, rc::Rc};
+
+/// Calls nested deeper than this are reported as a stack overflow.
+const FRAMES_MAX: usize = 4096;
+
+/// A function compiled for the VM together with the variables it captured
+/// and the globals of the module it was created in.
+pub struct Closure {
+    pub prototype: Rc<Prototype>,
+    pub upvalues: Vec<Rc<RefCell<Value>>>,
+    pub globals: Rc<RefCell<Environment>>,
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.prototype.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<fn>"),
+        }
+    }
+}
+
+/// Lets builtins such as `map()` call back into compiled functions.
+impl LoxCallable for Rc<Closure> {
+    fn arity(&self) -> RangeInclusive<usize> {
+        self.prototype.arity..=self.prototype.arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _paren: &Span,
+        arguments: Vec<Data>,
+    ) -> Result<Data, Error> {
+        call(interpreter, self.clone(), arguments)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Stack index of slot zero, which holds the closure being called.
+    base: usize,
+    cells: Vec<Rc<RefCell<Value>>>,
+    /// Heights of the interpreter's iterator stack and this run's handler
+    /// stack when the call started.
+    iterators: usize,
+    handlers: usize,
+}
+
+/// An active `try` block: where to resume and what to unwind to when an
+/// error is raised.
+struct Handler {
+    frames: usize,
+    stack: usize,
+    iterators: usize,
+    target: usize,
+}
+
+struct Vm<'a> {
+    interpreter: &'a mut Interpreter,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+}
+
+/// The stack and frames of a run that is waiting for a call into other code
+/// to return, parked where the collector can see them.
+#[derive(Clone, Debug)]
+pub(crate) struct Suspended {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+impl Suspended {
+    /// Calls `visit` with every object the run holds.
+    pub(crate) fn trace(&self, visit: &mut dyn FnMut(Object)) {
+        self.stack
+            .iter()
+            .filter_map(|value| Object::of(&value.get()))
+            .for_each(&mut *visit);
+        for frame in &self.frames {
+            visit(Object::Closure(frame.closure.clone()));
+            frame
+                .cells
+                .iter()
+                .cloned()
+                .map(Object::Cell)
+                .for_each(&mut *visit);
+        }
+    }
+}
+
+/// Compiles `statements` and runs them in the interpreter's current
+/// environment, reporting errors the same way `Interpreter::interpret` does.
+pub fn interpret(interpreter: &mut Interpreter, statements: &[Stmt]) {
+    match compiler::compile(statements) {
+        Ok(script) => interpret_compiled(interpreter, script),
+        Err(err) => eprintln!("Error: {}", err),
+    }
+}
+
+/// Runs an already compiled script, such as one loaded from a `.loxc` file,
+/// in the interpreter's current environment.
+pub fn interpret_compiled(interpreter: &mut Interpreter, script: Rc<Prototype>) {
+    let globals = interpreter.env.clone();
+    match run(interpreter, script, globals) {
+        Ok(()) => (),
+        Err(Error::Exit(code)) => interpreter.exit_code = Some(code),
+        Err(err) => eprintln!("Error: {}", err),
+    }
+}
+
+/// Runs a compiled script or module with `globals` as its top-level scope.
+pub fn run(
+    interpreter: &mut Interpreter,
+    prototype: Rc<Prototype>,
+    globals: Rc<RefCell<Environment>>,
+) -> Result<(), Error> {
+    let closure = Rc::new(Closure {
+        prototype,
+        upvalues: Vec::new(),
+        globals,
+    });
+    interpreter.track(Object::Closure(closure.clone()));
+    call(interpreter, closure, Vec::new()).map(|_| ())
+}
+
+/// Runs `closure` to completion on a fresh stack. The caller has checked the
+/// number of arguments.
+pub fn call(
+    interpreter: &mut Interpreter,
+    closure: Rc<Closure>,
+    arguments: Vec<Data>,
+) -> Result<Data, Error> {
+    let iterators = interpreter.iterators.len();
+    let mut vm = Vm {
+        interpreter,
+        stack: Vec::with_capacity(256),
+        frames: Vec::new(),
+        handlers: Vec::new(),
+    };
+    vm.push(Data::Closure(closure.clone()));
+    vm.stack.extend(arguments.into_iter().map(Value::from));
+    vm.push_frame(closure, 0);
+    let result = vm.run();
+    // An error leaves the loops it escaped behind.
+    vm.interpreter.iterators.truncate(iterators);
+    result
+}
+
+fn run_module(
+    interpreter: &mut Interpreter,
+    statements: &[Stmt],
+    env: Rc<RefCell<Environment>>,
+) -> Result<(), Error> {
+    let prototype = compiler::compile(statements).map_err(|err| {
+        let token = Span::synthetic(TokenType::Import, "import", err.line());
+        Error::ImportError(token, err.to_string())
+    })?;
+    run(interpreter, prototype, env)
+}
+
+impl<'a> Vm<'a> {
+    fn run(&mut self) -> Result<Data, Error> {
+        loop {
+            match self.execute() {
+                Ok(value) => return Ok(value),
+                Err(err) => self.catch(err)?,
+            }
+        }
+    }
+
+    /// Unwinds to the innermost `try` block of this run and pushes the
+    /// caught value for its handler. Errors this run can't handle are
+    /// returned to the caller.
+    fn catch(&mut self, err: Error) -> Result<(), Error> {
+        let handler = match self.handlers.pop() {
+            Some(handler) => handler,
+            None => return Err(err),
+        };
+        let value = err.into_value(self.interpreter)?;
+        self.frames.truncate(handler.frames);
+        self.stack.truncate(handler.stack);
+        self.interpreter.iterators.truncate(handler.iterators);
+        self.push(value);
+        self.frames.last_mut().unwrap().ip = handler.target;
+        Ok(())
+    }
+
+    fn push_frame(&mut self, closure: Rc<Closure>, base: usize) {
+        let cells = (0..closure.prototype.cells)
+            .map(|_| self.new_cell(Data::Nil))
+            .collect();
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base,
+            cells,
+            iterators: self.interpreter.iterators.len(),
+            handlers: self.handlers.len(),
+        });
+    }
+
+    /// Backward jumps and calls are the VM's safe points: every value the
+    /// run holds is on its stack or in its frames.
+    fn safe_point(&mut self) {
+        if self.interpreter.heap.is_due() {
+            self.suspended(Interpreter::collect);
+        }
+    }
+
+    /// Parks this run's stack and frames in the interpreter while `f` runs,
+    /// so a collection in other code sees what the run holds.
+    fn suspended<T>(&mut self, f: impl FnOnce(&mut Interpreter) -> T) -> T {
+        let stack = std::mem::take(&mut self.stack);
+        let frames = std::mem::take(&mut self.frames);
+        self.interpreter.suspended.push(Suspended { stack, frames });
+        let result = f(self.interpreter);
+        let run = self.interpreter.suspended.pop().unwrap();
+        self.stack = run.stack;
+        self.frames = run.frames;
+        result
+    }
+
+    /// Runs until the outermost frame returns or an error is raised.
+    fn execute(&mut self) -> Result<Data, Error> {
+        loop {
+            let op = OpCode::from_byte(self.read_byte()).expect("Unknown opcode.");
+            match op {
+                OpCode::Constant => {
+                    let value = match self.read_constant() {
+                        Constant::Number(num) => Data::Number(num),
+                        Constant::Str(str) => Data::Str(str),
+                        Constant::Function(_) | Constant::Name(_) => Data::Nil,
+                    };
+                    self.push(value);
+                }
+                OpCode::Nil => self.push(Data::Nil),
+                OpCode::True => self.push(Data::Bool(true)),
+                OpCode::False => self.push(Data::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack[slot] = self.peek().clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_name();
+                    let value = self.frame().closure.globals.borrow().get_value(name);
+                    match value {
+                        Some(value) => self.push(value),
+                        None => {
+                            let name = self.token(TokenType::Identifier, name.as_str());
+                            return Err(Error::UndefinedVariable(name));
+                        }
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    self.frame()
+                        .closure
+                        .globals
+                        .borrow_mut()
+                        .define(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name();
+                    let value = self.peek().get();
+                    let globals = self.frame().closure.globals.clone();
+                    if !globals.borrow_mut().assign_value(name, value) {
+                        let name = self.token(TokenType::Identifier, name.as_str());
+                        return Err(Error::UndefinedVariable(name));
+                    }
+                }
+                OpCode::GetCell => {
+                    let cell = self.read_byte() as usize;
+                    let value = self.frame().cells[cell].borrow().clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetCell => {
+                    let cell = self.read_byte() as usize;
+                    let value = self.peek().clone();
+                    *self.frame().cells[cell].borrow_mut() = value;
+                }
+                OpCode::DefineCell => {
+                    let cell = self.read_byte() as usize;
+                    let value = self.pop();
+                    self.frame_mut().cells[cell] = self.new_cell(value);
+                }
+                OpCode::NewCell => {
+                    let cell = self.read_byte() as usize;
+                    self.frame_mut().cells[cell] = self.new_cell(Data::Nil);
+                }
+                OpCode::GetUpvalue => {
+                    let upvalue = self.read_byte() as usize;
+                    let value = self.frame().closure.upvalues[upvalue].borrow().clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let upvalue = self.read_byte() as usize;
+                    let value = self.peek().clone();
+                    *self.frame().closure.upvalues[upvalue].borrow_mut() = value;
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let object = self.pop();
+                    let name = self.token(TokenType::Identifier, name.as_str());
+                    self.push(get_property(&name, &object)?);
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let bracket = self.recorded();
+                    self.push(get_index(&bracket, &object, &index)?);
+                }
+                OpCode::SetIndex | OpCode::SetIndexOp => {
+                    let operator = match op {
+                        OpCode::SetIndexOp => {
+                            let operator = OpCode::from_byte(self.read_byte());
+                            Some(self.operator(operator.expect("Unknown operator.")))
+                        }
+                        _ => None,
+                    };
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    let bracket = self.recorded();
+                    let operator = operator.as_ref();
+                    let value =
+                        set_index(self.interpreter, &bracket, &object, &index, operator, value)?;
+                    self.push(value);
+                }
+                OpCode::Equal | OpCode::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let equal = is_equal(left, right);
+                    self.push(Data::Bool(equal == (op == OpCode::Equal)));
+                }
+                OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::Modulo => self.binary(op)?,
+                OpCode::Not => {
+                    let value = self.stack.pop().unwrap();
+                    self.push(Data::Bool(value.is_falsey()));
+                }
+                OpCode::Negate => match self.pop() {
+                    Data::Number(num) => self.push(Data::Number(-num)),
+                    value => {
+                        return Err(Error::OperandNumberError(
+                            self.operator(OpCode::Subtract),
+                            String::new(),
+                            value.to_string(),
+                        ))
+                    }
+                },
+                OpCode::Print => println!("{}", self.pop()),
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if self.peek().is_falsey() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                    self.safe_point();
+                }
+                OpCode::Call => {
+                    let count = self.read_byte() as usize;
+                    self.call_value(count)?;
+                }
+                OpCode::Closure => {
+                    let prototype = match self.read_constant() {
+                        Constant::Function(prototype) => prototype,
+                        _ => unreachable!("Closure operand is not a function."),
+                    };
+                    let frame = self.frame();
+                    let upvalues = prototype
+                        .captures
+                        .iter()
+                        .map(|capture| match *capture {
+                            Capture::Cell(cell) => frame.cells[cell as usize].clone(),
+                            Capture::Upvalue(upvalue) => {
+                                frame.closure.upvalues[upvalue as usize].clone()
+                            }
+                        })
+                        .collect();
+                    let closure = Closure {
+                        prototype,
+                        upvalues,
+                        globals: frame.closure.globals.clone(),
+                    };
+                    let closure = Rc::new(closure);
+                    self.interpreter.track(Object::Closure(closure.clone()));
+                    self.push(Data::Closure(closure));
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    self.interpreter.iterators.truncate(frame.iterators);
+                    self.handlers.truncate(frame.handlers);
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.push(result);
+                }
+                OpCode::List => {
+                    let count = self.read_u16() as usize;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    let list = new_list(self.interpreter, elements);
+                    self.push(list);
+                }
+                OpCode::Map => {
+                    let count = self.read_u16() as usize;
+                    let entries = self.stack.split_off(self.stack.len() - 2 * count);
+                    let brace = self.recorded();
+                    let mut map = LoxMap::new();
+                    let mut entries = entries.into_iter();
+                    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+                        map.insert(map_key(&brace, &key.into_data())?, value.into_data());
+                    }
+                    let map = new_map(self.interpreter, map);
+                    self.push(map);
+                }
+                OpCode::IterStart => {
+                    let iterable = self.pop();
+                    let keyword = self.token(TokenType::In, "in");
+                    let iterator = LoxIterator::new(self.interpreter, &keyword, iterable)?;
+                    self.interpreter.iterators.push(iterator);
+                }
+                OpCode::IterNext => {
+                    let offset = self.read_u16() as usize;
+                    let keyword = self.token(TokenType::In, "in");
+                    match self.suspended(|interpreter| LoxIterator::next(interpreter, &keyword))? {
+                        Some(value) => self.push(value),
+                        None => self.frame_mut().ip += offset,
+                    }
+                }
+                OpCode::IterEnd => {
+                    self.interpreter.iterators.pop();
+                }
+                OpCode::Import => {
+                    // The path comes from the recorded string token, as in the
+                    // tree-walker.
+                    self.read_name();
+                    let token = self.recorded();
+                    let module =
+                        self.suspended(|interpreter| interpreter.import(&token, run_module))?;
+                    self.push(Data::Module(module));
+                }
+                OpCode::Throw => {
+                    let value = self.pop();
+                    return Err(Error::Thrown(self.token(TokenType::Throw, "throw"), value));
+                }
+                OpCode::PushHandler => {
+                    let offset = self.read_u16() as usize;
+                    let target = self.frame().ip + offset;
+                    let slots = self.read_u16() as usize;
+                    self.handlers.push(Handler {
+                        frames: self.frames.len(),
+                        stack: self.frame().base + slots,
+                        iterators: self.interpreter.iterators.len(),
+                        target,
+                    });
+                }
+                OpCode::PopHandler => {
+                    self.handlers.pop();
+                }
+            }
+        }
+    }
+
+    /// Compiled functions get a new frame on this run's stack; anything else
+    /// goes through `Interpreter::call`.
+    fn call_value(&mut self, count: usize) -> Result<(), Error> {
+        let base = self.stack.len() - count - 1;
+        let callee = self.stack[base].get();
+        if let Data::Closure(closure) = callee {
+            let arity = closure.prototype.arity;
+            if arity != count {
+                let paren = self.token(TokenType::RightParen, ")");
+                return Err(Error::ArityError(paren, arity..=arity, count));
+            }
+            if self.frames.len() >= FRAMES_MAX {
+                let paren = self.token(TokenType::RightParen, ")");
+                return Err(Error::StackOverflow(paren));
+            }
+            self.push_frame(closure, base);
+            self.safe_point();
+            return Ok(());
+        }
+        let arguments = self.stack.split_off(base + 1);
+        let arguments = arguments.into_iter().map(Value::into_data).collect();
+        self.stack.pop();
+        let paren = self.token(TokenType::RightParen, ")");
+        let result = self.suspended(|interpreter| interpreter.call(&paren, &callee, arguments))?;
+        self.push(result);
+        Ok(())
+    }
+
+    /// Arithmetic and comparisons on numbers run inline; everything else,
+    /// including the errors, is left to `Interpreter::operate`.
+    fn binary(&mut self, op: OpCode) -> Result<(), Error> {
+        let right = self.stack.pop().unwrap();
+        let left = self.stack.pop().unwrap();
+        let value = match (op, left.as_number(), right.as_number()) {
+            (OpCode::Add, Some(a), Some(b)) => Data::Number(a + b),
+            (OpCode::Subtract, Some(a), Some(b)) => Data::Number(a - b),
+            (OpCode::Multiply, Some(a), Some(b)) => Data::Number(a * b),
+            (OpCode::Divide, Some(a), Some(b)) => Data::Number(a / b),
+            (OpCode::Greater, Some(a), Some(b)) => Data::Bool(a > b),
+            (OpCode::GreaterEqual, Some(a), Some(b)) => Data::Bool(a >= b),
+            (OpCode::Less, Some(a), Some(b)) => Data::Bool(a < b),
+            (OpCode::LessEqual, Some(a), Some(b)) => Data::Bool(a <= b),
+            _ => {
+                let operator = self.operator(op);
+                let (left, right) = (left.into_data(), right.into_data());
+                self.interpreter.operate(&operator, left, right)?
+            }
+        };
+        self.push(value);
+        Ok(())
+    }
+
+    fn new_cell(&mut self, value: Data) -> Rc<RefCell<Value>> {
+        let cell = Rc::new(RefCell::new(value.into()));
+        self.interpreter.track(Object::Cell(cell.clone()));
+        cell
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.closure.prototype.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let high = self.read_byte();
+        let low = self.read_byte();
+        u16::from_be_bytes([high, low])
+    }
+
+    fn read_constant(&mut self) -> Constant {
+        let index = self.read_u16() as usize;
+        self.frame().closure.prototype.chunk.constants[index].clone()
+    }
+
+    fn read_name(&mut self) -> Symbol {
+        match self.read_constant() {
+            Constant::Name(name) => name,
+            _ => unreachable!("Name operand is not a name."),
+        }
+    }
+
+    fn push(&mut self, value: Data) {
+        self.stack.push(value.into());
+    }
+
+    fn pop(&mut self) -> Data {
+        self.stack.pop().unwrap().into_data()
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().unwrap()
+    }
+
+    /// The line of the instruction being executed.
+    fn line(&self) -> usize {
+        let frame = self.frame();
+        frame.closure.prototype.chunk.lines[frame.ip - 1]
+    }
+
+    /// Most spans aren't kept in the bytecode, so one is made up from the
+    /// current line.
+    fn token(&self, ttype: TokenType, lexeme: &'static str) -> Span {
+        Span::synthetic(ttype, lexeme, self.line())
+    }
+
+    /// The token the compiler recorded for the instruction just read. The
+    /// loader rejects files whose instructions are missing one.
+    fn recorded(&self) -> Span {
+        let frame = self.frame();
+        let chunk = &frame.closure.prototype.chunk;
+        chunk
+            .span(frame.ip)
+            .expect("Instruction has no source token.")
+    }
+
+    fn operator(&self, op: OpCode) -> Span {
+        let (ttype, lexeme) = match op {
+            OpCode::Add => (TokenType::Plus, "+"),
+            OpCode::Subtract => (TokenType::Minus, "-"),
+            OpCode::Multiply => (TokenType::Star, "*"),
+            OpCode::Divide => (TokenType::Slash, "/"),
+            OpCode::Modulo => (TokenType::Percent, "%"),
+            OpCode::Greater => (TokenType::Greater, ">"),
+            OpCode::GreaterEqual => (TokenType::GreaterEqual, ">="),
+            OpCode::Less => (TokenType::Less, "<"),
+            OpCode::LessEqual => (TokenType::LessEqual, "<="),
+            OpCode::NotEqual => (TokenType::BangEqual, "!="),
+            _ => (TokenType::EqualEqual, "=="),
+        };
+        self.token(ttype, lexeme)
+    }
+}
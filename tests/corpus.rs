@@ -0,0 +1,62 @@
+//! Runs every script in `tests/corpus` on both backends, with and without the
+//! optimizer and with the collector running at every safe point, and checks
+//! that each prints exactly what `name.out` next to the script expects: its
+//! output, then its error lines, then its exit code if that isn't 0.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn rlox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .output()
+        .expect("Unable to run rlox")
+}
+
+/// What a run is compared by.
+fn transcript(output: &Output) -> String {
+    let mut transcript = String::from_utf8_lossy(&output.stdout).into_owned();
+    transcript.push_str(&String::from_utf8_lossy(&output.stderr));
+    match output.status.code() {
+        Some(0) => {}
+        code => transcript.push_str(&format!("exit: {:?}\n", code)),
+    }
+    transcript
+}
+
+#[test]
+fn test_corpus_output() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut scripts: Vec<_> = fs::read_dir(corpus)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    for script in scripts {
+        let expected = fs::read_to_string(script.with_extension("out"))
+            .unwrap_or_else(|_| panic!("{} has no .out file", script.display()));
+        let script = script.to_str().unwrap();
+        let configs = [
+            &[][..],
+            &["--vm"],
+            &["--optimize"],
+            &["--optimize", "--vm"],
+            &["--gc-stress"],
+            &["--gc-stress", "--vm"],
+        ];
+        for flags in configs {
+            let actual = rlox(&[flags, &[script]].concat());
+            assert_eq!(
+                transcript(&actual),
+                expected,
+                "output differs for {} {:?}",
+                script,
+                flags
+            );
+        }
+    }
+}